@@ -0,0 +1,51 @@
+//! 取指-执行主循环每秒能执行多少条指令。
+//! 程序是一个不停机的纯计算循环，不用trap也不碰设备寄存器，测的只是step_once本身；
+//! 给热路径加检查(断点、trace、设备轮询)之前和之后各跑一次对比。
+//!
+//!     cargo bench --bench execute
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use lc_3_vm::inline_asm::assemble_line;
+use lc_3_vm::{StepResult, Vm};
+
+/// 每次迭代执行的指令数
+const STEPS: u64 = 100_000;
+
+const PROGRAM: &[&str] = &[
+    "AND R0, R0, #0",
+    "AND R2, R2, #0",
+    "LEA R1, #7",
+    // LOOP: 算术、访存和分支都有
+    "ADD R0, R0, #1",
+    "LDR R3, R1, #0",
+    "ADD R3, R3, R0",
+    "STR R3, R1, #0",
+    "NOT R4, R3",
+    "AND R2, R2, R4",
+    "BRnzp #-7",
+    // DATA
+    ".FILL x0000",
+];
+
+fn compute_loop() -> Vm {
+    let mut vm = Vm::new();
+    for (i, line) in PROGRAM.iter().enumerate() {
+        vm.memory[0x3000 + i] = assemble_line(line).unwrap().unwrap();
+    }
+    vm
+}
+
+fn bench_step_once(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execute");
+    group.throughput(Throughput::Elements(STEPS));
+    group.bench_function("compute_loop", |b| {
+        let mut vm = compute_loop();
+        b.iter(|| {
+            assert_eq!(vm.step_n(black_box(STEPS)), StepResult::Continued);
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_step_once);
+criterion_main!(benches);
@@ -0,0 +1,367 @@
+//! 把一段汇编源码编成可以直接加载的目标代码，主要给测试写小程序用。
+//! 每一行的解析和编码都复用inline_asm，这里处理label和.ORIG/.END/.BLKW/.STRINGZ这类伪指令。
+//! 分两遍：第一遍算出每一行的地址并记下所有label，第二遍把label换算成偏移量或地址再编码。
+//! 输出的第一个字是起始地址，后面是指令字，和.obj文件的内容一致。
+
+use crate::inline_asm::{
+    encode, is_mnemonic, parse_line, parse_number, parse_operand, strip_comment, tokenize,
+    LineError, Operand, Stmt,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// 汇编失败的原因，行号从1开始
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    /// 某一行本身有错，未定义的label也在这里(LineError::UnresolvedLabel)
+    Line { line: usize, error: LineError },
+    /// 第一条语句之前没有.ORIG
+    MissingOrig { line: usize },
+    /// .ORIG的地址不对，或者出现了不止一次
+    BadOrig { line: usize, token: String },
+    /// 同一个label定义了两次
+    DuplicateLabel { line: usize, label: String },
+    /// label离引用它的指令太远，偏移量放不进指令的字段
+    LabelOutOfRange {
+        line: usize,
+        label: String,
+        offset: i32,
+    },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::Line { line, error } => write!(f, "line {}: {}", line, error),
+            AsmError::MissingOrig { line } => {
+                write!(
+                    f,
+                    "line {}: expected .ORIG before the first statement",
+                    line
+                )
+            }
+            AsmError::BadOrig { line, token } => {
+                write!(f, "line {}: bad .ORIG '{}'", line, token)
+            }
+            AsmError::DuplicateLabel { line, label } => {
+                write!(f, "line {}: label '{}' is already defined", line, label)
+            }
+            AsmError::LabelOutOfRange {
+                line,
+                label,
+                offset,
+            } => write!(
+                f,
+                "line {}: label '{}' is out of range (offset {})",
+                line, label, offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// 汇编得到的label到地址的对应关系
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: BTreeMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记下一个label，已经有同名的label时返回原来的地址并覆盖掉
+    pub fn insert(&mut self, label: &str, addr: u16) -> Option<u16> {
+        self.symbols.insert(label.to_string(), addr)
+    }
+
+    /// label的地址
+    pub fn address(&self, label: &str) -> Option<u16> {
+        self.symbols.get(label).copied()
+    }
+
+    /// 这个地址上的label，有好几个时取名字排在最前的
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .find(|&(_, &a)| a == addr)
+            .map(|(label, _)| label.as_str())
+    }
+
+    /// 按名字顺序遍历
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.symbols
+            .iter()
+            .map(|(label, &addr)| (label.as_str(), addr))
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// 读回to_text的格式，空行忽略，有任何一行不对时返回None
+    pub fn from_text(text: &str) -> Option<SymbolTable> {
+        let mut table = SymbolTable::new();
+        for line in text.lines() {
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [] => {}
+                [label, addr] => {
+                    let addr = parse_number(addr).filter(|v| (0..=0xFFFF).contains(v))?;
+                    table.insert(label, addr as u16);
+                }
+                _ => return None,
+            }
+        }
+        Some(table)
+    }
+
+    /// 按地址排列，每行一个label和地址
+    pub fn to_text(&self) -> String {
+        let mut rows = self.iter().collect::<Vec<_>>();
+        rows.sort_by_key(|&(_, addr)| addr);
+        rows.iter()
+            .map(|(label, addr)| format!("{:<20} {:#06x}\n", label, addr))
+            .collect()
+    }
+}
+
+/// 第一遍得到的一行：地址定了，内容要么还等着换算label，要么已经是现成的字
+struct Item {
+    line: usize,
+    addr: u16,
+    body: Body,
+}
+
+enum Body {
+    Stmt(Stmt),
+    Words(Vec<u16>),
+}
+
+/// 汇编整段源码，返回起始地址加上每个字
+pub fn assemble(source: &str) -> Result<Vec<u16>, AsmError> {
+    assemble_with_listing(source).map(|(words, _, _)| words)
+}
+
+/// 和assemble一样，另外返回符号表和清单。
+/// 清单逐行列出源码，前面是这一行的地址和生成的字；
+/// .BLKW/.STRINGZ生成好几个字时，后面的字各占一行，只有地址和字
+pub fn assemble_with_listing(source: &str) -> Result<(Vec<u16>, SymbolTable, String), AsmError> {
+    let (origin, items, symbols) = first_pass(source)?;
+
+    let mut words = vec![origin];
+    // 每一行源码生成的地址和字
+    let mut generated: HashMap<usize, (u16, Vec<u16>)> = HashMap::new();
+    for item in items {
+        let data = match item.body {
+            Body::Words(data) => data,
+            Body::Stmt(stmt) => vec![encode_item(item.line, item.addr, stmt, &symbols)?],
+        };
+        words.extend(&data);
+        generated.insert(item.line, (item.addr, data));
+    }
+
+    let mut listing = String::new();
+    for (index, text) in source.lines().enumerate() {
+        match generated.get(&(index + 1)) {
+            Some((addr, data)) if !data.is_empty() => {
+                listing.push_str(&format!("{:#06x}  {:#06x}  {}\n", addr, data[0], text));
+                for (offset, word) in data.iter().enumerate().skip(1) {
+                    let addr = addr.wrapping_add(offset as u16);
+                    listing.push_str(&format!("{:#06x}  {:#06x}\n", addr, word));
+                }
+            }
+            _ => listing.push_str(&format!("{:16}{}\n", "", text)),
+        }
+    }
+
+    Ok((words, symbols, listing))
+}
+
+/// 转成大端序字节，可以直接写成.obj文件或者交给read_image_from_bytes
+pub fn to_bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+/// 给每一行分配地址，记下label，返回起始地址
+fn first_pass(source: &str) -> Result<(u16, Vec<Item>, SymbolTable), AsmError> {
+    let mut origin: Option<u16> = None;
+    let mut addr: u16 = 0;
+    let mut items = vec![];
+    let mut symbols = SymbolTable::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let tokens = tokenize(text);
+        let Some(&first) = tokens.first() else {
+            continue;
+        };
+
+        // 行首不是助记符就是label，后面可以什么都没有
+        let (label, rest, tokens) = if is_mnemonic(first) {
+            (None, text, &tokens[..])
+        } else {
+            let trimmed = text.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+            let label = first.strip_suffix(':').unwrap_or(first);
+            (Some(label), &trimmed[first.len()..], &tokens[1..])
+        };
+
+        let directive = tokens.first().map(|t| t.to_ascii_uppercase());
+        if directive.as_deref() == Some(".ORIG") {
+            let token = tokens[1..].join(" ");
+            let value = match tokens[1..] {
+                [addr] => parse_number(addr).filter(|v| (0..=0xFFFF).contains(v)),
+                _ => None,
+            };
+            match value {
+                Some(value) if origin.is_none() => {
+                    origin = Some(value as u16);
+                    addr = value as u16;
+                }
+                _ => return Err(AsmError::BadOrig { line, token }),
+            }
+            continue;
+        }
+        // .END之后的内容都忽略
+        if directive.as_deref() == Some(".END") {
+            break;
+        }
+
+        if origin.is_none() {
+            return Err(AsmError::MissingOrig { line });
+        }
+        if let Some(label) = label {
+            if !matches!(parse_operand(label), Ok(Operand::Label(_))) {
+                return Err(AsmError::Line {
+                    line,
+                    error: LineError::BadOperand(label.to_string()),
+                });
+            }
+            if symbols.insert(label, addr).is_some() {
+                return Err(AsmError::DuplicateLabel {
+                    line,
+                    label: label.to_string(),
+                });
+            }
+        }
+
+        let line_error = |error| AsmError::Line { line, error };
+        let body = match directive.as_deref() {
+            None => continue,
+            Some(".BLKW") => {
+                let count = match tokens[1..] {
+                    [count] => parse_number(count)
+                        .filter(|n| (0..=0xFFFF).contains(n))
+                        .ok_or_else(|| line_error(LineError::BadOperand(count.to_string())))?,
+                    _ => {
+                        return Err(line_error(LineError::OperandCount {
+                            mnemonic: ".BLKW".to_string(),
+                            expected: 1,
+                            found: tokens.len() - 1,
+                        }))
+                    }
+                };
+                Body::Words(vec![0; count as usize])
+            }
+            Some(".STRINGZ") => {
+                let mut data = parse_string(rest).map_err(line_error)?;
+                data.push(0);
+                Body::Words(data)
+            }
+            Some(_) => Body::Stmt(
+                parse_line(rest)
+                    .map_err(line_error)?
+                    .expect("line has tokens"),
+            ),
+        };
+
+        let size = match &body {
+            Body::Words(data) => data.len(),
+            Body::Stmt(_) => 1,
+        };
+        items.push(Item { line, addr, body });
+        addr = addr.wrapping_add(size as u16);
+    }
+
+    match origin {
+        Some(origin) => Ok((origin, items, symbols)),
+        None => Err(AsmError::MissingOrig {
+            line: source.lines().count() + 1,
+        }),
+    }
+}
+
+/// 把语句里的label换算掉再编码：PC相对寻址的指令换成相对下一条指令的偏移量，.FILL换成地址
+fn encode_item(line: usize, addr: u16, stmt: Stmt, symbols: &SymbolTable) -> Result<u16, AsmError> {
+    let lookup = |label: &str| {
+        symbols.address(label).ok_or_else(|| AsmError::Line {
+            line,
+            error: LineError::UnresolvedLabel(label.to_string()),
+        })
+    };
+
+    let mut label = None;
+    let stmt = match stmt {
+        Stmt::Fill(Operand::Label(name)) => Stmt::Fill(Operand::Imm(lookup(&name)? as i32)),
+        Stmt::Instr {
+            mnemonic,
+            mut operands,
+        } if is_pc_relative(&mnemonic) => {
+            if let Some(Operand::Label(name)) = operands.last() {
+                let offset = lookup(name)? as i32 - (addr as i32 + 1);
+                label = Some(name.clone());
+                *operands.last_mut().unwrap() = Operand::Imm(offset);
+            }
+            Stmt::Instr { mnemonic, operands }
+        }
+        stmt => stmt,
+    };
+
+    encode(&stmt).map_err(|error| match (error, label) {
+        (LineError::OutOfRange { value, .. }, Some(label)) => AsmError::LabelOutOfRange {
+            line,
+            label,
+            offset: value,
+        },
+        (error, _) => AsmError::Line { line, error },
+    })
+}
+
+/// 最后一个操作数是PC相对偏移量的指令
+fn is_pc_relative(mnemonic: &str) -> bool {
+    mnemonic.starts_with("BR") || matches!(mnemonic, "LD" | "LDI" | "LEA" | "ST" | "STI" | "JSR")
+}
+
+/// 取出.STRINGZ后面双引号里的字符串，支持\n \t \0 \\ \"这几种转义，每个字符一个字
+fn parse_string(rest: &str) -> Result<Vec<u16>, LineError> {
+    let bad = || LineError::BadOperand(strip_comment(rest).trim().to_string());
+    let start = rest.find('"').ok_or_else(bad)?;
+
+    let mut data = vec![];
+    let mut chars = rest[start + 1..].chars();
+    loop {
+        let c = match chars.next().ok_or_else(bad)? {
+            '"' => break,
+            '\\' => match chars.next().ok_or_else(bad)? {
+                'n' => '\n',
+                't' => '\t',
+                '0' => '\0',
+                c @ ('\\' | '"') => c,
+                _ => return Err(bad()),
+            },
+            c => c,
+        };
+        data.push(c as u16);
+    }
+
+    // 引号后面只能是注释
+    if !strip_comment(chars.as_str()).trim().is_empty() {
+        return Err(bad());
+    }
+    Ok(data)
+}
@@ -0,0 +1,84 @@
+//! 条件断点：`0x3010 if R2 == 0xFFFF` 这样的断点只有条件成立时才停。
+//! 条件只有一个比较：寄存器、比较符和一个数，在执行这条指令之前对寄存器求值。
+
+use crate::inline_asm::parse_number;
+use crate::register::{Reg, Registers};
+use crate::to_signed;
+
+/// 比较符。==和!=按16位的值比较，大小比较按有符号数，这样 `R0 < 0` 表示负数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// 断点的条件：reg op value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    /// R0-R7或者PC在寄存器表里的下标
+    pub reg: usize,
+    pub op: CompareOp,
+    pub value: u16,
+}
+
+impl Condition {
+    /// 解析 `R2 == 0xFFFF` 这样的条件，寄存器不区分大小写，
+    /// 数字的写法和汇编器一样(#十进制、x/0x十六进制、负数)。格式不对时返回None
+    pub fn parse(text: &str) -> Option<Condition> {
+        let tokens = text.split_whitespace().collect::<Vec<_>>();
+        let [reg, op, value] = tokens.as_slice() else {
+            return None;
+        };
+
+        let reg = match reg.to_ascii_uppercase().as_str() {
+            "PC" => Reg::PC as usize,
+            name => match name.strip_prefix('R')?.parse::<usize>() {
+                Ok(r) if r <= 7 => r,
+                _ => return None,
+            },
+        };
+        let op = match *op {
+            "==" => CompareOp::Eq,
+            "!=" => CompareOp::Ne,
+            "<" => CompareOp::Lt,
+            "<=" => CompareOp::Le,
+            ">" => CompareOp::Gt,
+            ">=" => CompareOp::Ge,
+            _ => return None,
+        };
+        let value = match parse_number(value)? {
+            n @ -0x8000..=0xFFFF => n as u16,
+            _ => return None,
+        };
+
+        Some(Condition { reg, op, value })
+    }
+
+    /// 对当前的寄存器求值
+    pub fn holds(&self, reg: &Registers) -> bool {
+        let actual = reg[self.reg];
+        let (a, b) = (to_signed(actual), to_signed(self.value));
+        match self.op {
+            CompareOp::Eq => actual == self.value,
+            CompareOp::Ne => actual != self.value,
+            CompareOp::Lt => a < b,
+            CompareOp::Le => a <= b,
+            CompareOp::Gt => a > b,
+            CompareOp::Ge => a >= b,
+        }
+    }
+}
+
+/// 把 `<addr> [if <条件>]` 拆成地址部分和条件，地址怎么解析(数字还是label)由调用方决定。
+/// 有if但条件写错了时返回None
+pub fn split_spec(spec: &str) -> Option<(&str, Option<Condition>)> {
+    let spec = spec.trim();
+    match spec.split_once(" if ") {
+        Some((addr, condition)) => Some((addr.trim(), Some(Condition::parse(condition)?))),
+        None => Some((spec, None)),
+    }
+}
@@ -0,0 +1,210 @@
+//! 嵌入用的构造器：把镜像、起始地址、指令上限和输入输出一次配好再建出Vm，
+//! 不用拿到Vm之后一个字段一个字段地改。命令行参数解析在库这边的对应物。
+//!
+//! ```ignore
+//! let mut vm = VmBuilder::new()
+//!     .origin(0x3000)
+//!     .image_bytes(&data)
+//!     .max_instructions(100_000)
+//!     .input(reader)
+//!     .output(writer)
+//!     .build()?;
+//! vm.run()?;
+//! ```
+
+use crate::register::Reg;
+use crate::{overlap, Endian, EofPolicy, ImageError, Vm, DEVICE_PAGE_START, MEMORY_SIZE, PC_START};
+use std::fmt;
+use std::io::{Read, Write};
+use std::ops::Range;
+
+/// build()检查出来的配置问题
+#[derive(Debug)]
+pub enum BuildError {
+    /// 第index个镜像(从0数)加载失败，包括放不进内存
+    Image { index: usize, error: ImageError },
+    /// 两个镜像的加载范围重叠，后面的会覆盖前面的
+    Overlap {
+        first: usize,
+        second: usize,
+        range: Range<u32>,
+    },
+    /// 起始地址不在内存里，或者落在设备寄存器区
+    BadOrigin { origin: u16, memory_size: usize },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Image { index, error } => write!(f, "image {}: {}", index, error),
+            BuildError::Overlap {
+                first,
+                second,
+                range,
+            } => write!(
+                f,
+                "image {} overlaps image {} at {:#06x}-{:#06x}",
+                second,
+                first,
+                range.start,
+                range.end - 1
+            ),
+            BuildError::BadOrigin {
+                origin,
+                memory_size,
+            } => write!(
+                f,
+                "origin {:#06x} is outside program memory ({} words, devices from {:#06x})",
+                origin, memory_size, DEVICE_PAGE_START
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Vm的构造器，没设置的选项和Vm::new()一样
+pub struct VmBuilder {
+    memory_size: usize,
+    origin: Option<u16>,
+    /// 镜像数据和加载地址，地址是None时用镜像头里的origin
+    images: Vec<(Vec<u8>, Option<u16>)>,
+    endian: Endian,
+    max_instructions: Option<u64>,
+    input: Option<Box<dyn Read>>,
+    output: Option<Box<dyn Write>>,
+    use_os: bool,
+    on_eof: EofPolicy,
+}
+
+impl Default for VmBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VmBuilder {
+    pub fn new() -> Self {
+        VmBuilder {
+            memory_size: MEMORY_SIZE,
+            origin: None,
+            images: Vec::new(),
+            endian: Endian::default(),
+            max_instructions: None,
+            input: None,
+            output: None,
+            use_os: false,
+            on_eof: EofPolicy::default(),
+        }
+    }
+
+    /// 内存大小(字)，和Vm::with_memory_size一样
+    pub fn memory_size(mut self, words: usize) -> Self {
+        self.memory_size = words;
+        self
+    }
+
+    /// PC的初始值。不设置时从第一个镜像的开头开始，没有镜像时是0x3000
+    pub fn origin(mut self, origin: u16) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// 加一个带origin头的.obj镜像，可以调用多次按顺序加载
+    pub fn image_bytes(mut self, data: &[u8]) -> Self {
+        self.images.push((data.to_vec(), None));
+        self
+    }
+
+    /// 加一个不带头的裸镜像，放到addr，和命令行的--load一样
+    pub fn image_bytes_at(mut self, data: &[u8], addr: u16) -> Self {
+        self.images.push((data.to_vec(), Some(addr)));
+        self
+    }
+
+    /// 镜像的字节顺序，默认大端序
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    /// run/run_until_halt最多执行的指令数
+    pub fn max_instructions(mut self, n: u64) -> Self {
+        self.max_instructions = Some(n);
+        self
+    }
+
+    /// 键盘输入的来源，不设置时是stdin
+    pub fn input(mut self, input: impl Read + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    /// 显示器输出的去处，不设置时是stdout
+    pub fn output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// TRAP走内存里的trap向量表，和--use-os一样
+    pub fn use_os(mut self, use_os: bool) -> Self {
+        self.use_os = use_os;
+        self
+    }
+
+    /// 输入读完之后的处理方式，和--on-eof一样
+    pub fn on_eof(mut self, policy: EofPolicy) -> Self {
+        self.on_eof = policy;
+        self
+    }
+
+    /// 加载所有镜像并检查配置：每个镜像都要放得进内存、互不重叠，
+    /// 起始地址要在内存里而且不能落在设备寄存器区
+    pub fn build(self) -> Result<Vm, BuildError> {
+        let mut vm = Vm::with_memory_size(self.memory_size);
+        vm.endian = self.endian;
+
+        let mut loaded: Vec<Range<u32>> = Vec::new();
+        for (index, (data, addr)) in self.images.iter().enumerate() {
+            let range = vm
+                .read_image_from_bytes(data, *addr)
+                .map_err(|error| BuildError::Image { index, error })?;
+            if let Some((first, shared)) = loaded
+                .iter()
+                .enumerate()
+                .find_map(|(i, earlier)| overlap(earlier, &range).map(|r| (i, r)))
+            {
+                return Err(BuildError::Overlap {
+                    first,
+                    second: index,
+                    range: shared,
+                });
+            }
+            loaded.push(range);
+        }
+
+        let origin = self
+            .origin
+            .or_else(|| loaded.first().map(|r| r.start as u16))
+            .unwrap_or(PC_START);
+        if origin as usize >= vm.memory.len() || origin >= DEVICE_PAGE_START {
+            return Err(BuildError::BadOrigin {
+                origin,
+                memory_size: vm.memory.len(),
+            });
+        }
+        vm.origin = origin;
+        vm.reg[Reg::PC] = origin;
+
+        if let Some(input) = self.input {
+            vm.set_input(input);
+        }
+        if let Some(output) = self.output {
+            vm.sink = Some(output);
+        }
+        vm.max_instructions = self.max_instructions;
+        vm.use_os = self.use_os;
+        vm.on_eof = self.on_eof;
+        Ok(vm)
+    }
+}
@@ -0,0 +1,65 @@
+//! 这个虚拟机实现了LC-3 ISA的哪些部分，--version据此报告。
+//! 能从代码里推出来的(比如认识哪些trap)都直接推，加了新功能时输出会自动跟上；
+//! 推不出来的开关集中写在capabilities()里，实现对应功能时顺手改掉。
+
+use crate::disasm::{extension_trap_name, trap_name};
+
+/// 实现了的ISA功能
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub version: &'static str,
+    /// 内置实现的trap：vector和别名
+    pub traps: Vec<(u16, &'static str)>,
+    /// 加上--extensions才有的扩展trap
+    pub extension_traps: Vec<(u16, &'static str)>,
+    /// 特权模式和RTI
+    pub rti: bool,
+    /// 键盘中断
+    pub interrupts: bool,
+    /// 接上的内存映射设备
+    pub devices: Vec<&'static str>,
+}
+
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        traps: (0..=0xFF)
+            .filter_map(|vector| trap_name(vector).map(|name| (vector, name)))
+            .collect(),
+        extension_traps: (0..=0xFF)
+            .filter_map(|vector| extension_trap_name(vector).map(|name| (vector, name)))
+            .collect(),
+        rti: true,
+        interrupts: true,
+        devices: vec![
+            "keyboard (KBSR/KBDR)",
+            "display (DSR/DDR)",
+            "clock (0xFE08)",
+            "machine control (MCR)",
+        ],
+    }
+}
+
+impl Capabilities {
+    /// --version的输出
+    pub fn report(&self) -> String {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        let list = |traps: &[(u16, &str)]| {
+            traps
+                .iter()
+                .map(|(vector, name)| format!("{} ({:#04x})", name, vector))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        format!(
+            "lc-3_vm {}\nISA: LC-3\nTraps: {}\nExtension traps (--extensions): {}\nRTI: {}\nInterrupts: {}\nDevices: {}\n",
+            self.version,
+            list(&self.traps),
+            list(&self.extension_traps),
+            yes_no(self.rti),
+            yes_no(self.interrupts),
+            self.devices.join(", ")
+        )
+    }
+}
@@ -0,0 +1,46 @@
+//! --cycles用的粗略时序模型：每种opcode一个固定的周期数，
+//! 访问内存的指令比只动寄存器的贵，访问0xFE00以上的设备寄存器再额外加一笔。
+//! 数字只是相对的估计，用来比较同一个程序不同写法的快慢，不对应任何真实硬件。
+
+/// 每种opcode的周期数和访问设备的额外开销
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CyclesModel {
+    /// 下标是opcode(instr >> 12)
+    pub costs: [u8; 16],
+    /// 每次读写设备寄存器额外的周期数
+    pub device_penalty: u8,
+}
+
+impl Default for CyclesModel {
+    fn default() -> Self {
+        CyclesModel {
+            // BR ADD LD ST JSR AND LDR STR RTI NOT LDI STI JMP RES LEA TRAP
+            // 只动寄存器的是1，每访问一次内存加1；RTI要弹两个字，TRAP要读向量表
+            costs: [1, 1, 2, 2, 1, 1, 2, 2, 3, 1, 3, 3, 1, 0, 1, 2],
+            device_penalty: 4,
+        }
+    }
+}
+
+/// 按模型累计的周期数
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cycles {
+    pub model: CyclesModel,
+    pub total: u64,
+}
+
+impl Cycles {
+    pub fn new(model: CyclesModel) -> Self {
+        Cycles { model, total: 0 }
+    }
+
+    /// 执行了一条opcode
+    pub fn instruction(&mut self, opcode: u16) {
+        self.total += self.model.costs[opcode as usize] as u64;
+    }
+
+    /// 访问了一次设备寄存器
+    pub fn device_access(&mut self) {
+        self.total += self.model.device_penalty as u64;
+    }
+}
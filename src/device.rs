@@ -0,0 +1,103 @@
+//! 用户自己接的内存映射设备。键盘仍然是Vm::mem_read里内置的KBSR/KBDR，其余的设备(计数器、
+//! 用文件当后端的块设备等)实现MmioDevice，用Vm::map_device挂到自己选的一段地址上，
+//! 程序读写这段地址时Vm::mem_read/mem_write就转给它，不用改虚拟机的核心代码。
+
+use crate::{is_device_register, Vm};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// 一个内存映射设备，offset是相对映射起始地址的偏移
+pub trait MmioDevice {
+    fn read(&mut self, offset: u16) -> u16;
+    fn write(&mut self, offset: u16, val: u16);
+}
+
+/// map_device拒绝映射的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapError {
+    /// 范围是空的
+    Empty(RangeInclusive<u16>),
+    /// 和已经映射的设备或者内置的设备寄存器重叠
+    Overlap(RangeInclusive<u16>),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Empty(range) => write!(
+                f,
+                "device range {:#06x}-{:#06x} is empty",
+                range.start(),
+                range.end()
+            ),
+            MapError::Overlap(range) => write!(
+                f,
+                "device range {:#06x}-{:#06x} overlaps another device",
+                range.start(),
+                range.end()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+/// 一段映射：地址范围和接在上面的设备
+pub(crate) struct Mapping {
+    range: RangeInclusive<u16>,
+    device: Box<dyn MmioDevice>,
+}
+
+impl Vm {
+    /// 把device挂到range上。地址随便选，挂在普通内存上时这段内存就被设备盖住了；
+    /// 只是不能和内置的设备寄存器或者别的设备重叠
+    pub fn map_device(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Box<dyn MmioDevice>,
+    ) -> Result<(), MapError> {
+        if range.is_empty() {
+            return Err(MapError::Empty(range));
+        }
+        let taken = range
+            .clone()
+            .any(|addr| is_device_register(addr) || self.is_mapped(addr));
+        if taken {
+            return Err(MapError::Overlap(range));
+        }
+
+        self.devices.push(Mapping { range, device });
+        Ok(())
+    }
+
+    /// addr上是不是挂了map_device映射的设备
+    pub(crate) fn is_mapped(&self, addr: u16) -> bool {
+        self.devices
+            .iter()
+            .any(|mapping| mapping.range.contains(&addr))
+    }
+
+    /// 映射的设备读，addr上没有设备时返回None
+    pub(crate) fn read_mapped(&mut self, addr: u16) -> Option<u16> {
+        let mapping = self
+            .devices
+            .iter_mut()
+            .find(|mapping| mapping.range.contains(&addr))?;
+        Some(mapping.device.read(addr - mapping.range.start()))
+    }
+
+    /// 映射的设备写，addr上没有设备时返回false
+    pub(crate) fn write_mapped(&mut self, addr: u16, val: u16) -> bool {
+        match self
+            .devices
+            .iter_mut()
+            .find(|mapping| mapping.range.contains(&addr))
+        {
+            Some(mapping) => {
+                mapping.device.write(addr - mapping.range.start(), val);
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! 反汇编：把一个16位指令字还原成LC-3汇编文本。
+//! 字段的拆法和opcode.rs里的op_*函数完全一致，调试器和--disasm都用这里的结果。
+//! 有符号表时，PC相对寻址算出来的目标地址换成label显示，没有对应label的仍然显示十六进制。
+
+use crate::asm::SymbolTable;
+use crate::opcodes::{decode_opcode, OpCodes};
+use crate::TrapCode;
+use crate::{sign_extend, to_signed};
+use std::ops::Range;
+
+/// 反汇编一条指令，pc是这条指令自己所在的地址。
+/// PC相对寻址的指令(BR/LD/LDI/LEA/ST/STI/JSR)按执行时已经加1的PC算出绝对目标地址。
+pub fn disassemble(instr: u16, pc: u16) -> String {
+    disassemble_with(instr, pc, None)
+}
+
+/// 和disassemble一样，目标地址在符号表里有label时显示label
+pub fn disassemble_with(instr: u16, pc: u16, symbols: Option<&SymbolTable>) -> String {
+    let dr = (instr >> 9) & 0x7;
+    let sr1 = (instr >> 6) & 0x7;
+    let next_pc = pc.wrapping_add(1);
+    let target = |bits: u16| {
+        let addr = next_pc.wrapping_add(sign_extend(instr & ((1 << bits) - 1), bits));
+        match symbols.and_then(|symbols| symbols.label_at(addr)) {
+            Some(label) => label.to_string(),
+            None => format!("{:#06x}", addr),
+        }
+    };
+    let imm = |bits: u16| to_signed(sign_extend(instr & ((1 << bits) - 1), bits));
+
+    match decode_opcode(instr) {
+        // nzp都是0的BR永远不会跳转，就是NOP(全0的字也是这样)
+        OpCodes::OP_BR if instr & 0x0E00 == 0 => String::from("NOP"),
+        OpCodes::OP_BR => {
+            let mut name = String::from("BR");
+            if instr & 0x0800 != 0 {
+                name.push('n');
+            }
+            if instr & 0x0400 != 0 {
+                name.push('z');
+            }
+            if instr & 0x0200 != 0 {
+                name.push('p');
+            }
+            format!("{} {}", name, target(9))
+        }
+        op @ (OpCodes::OP_ADD | OpCodes::OP_AND) => {
+            let name = if op == OpCodes::OP_ADD { "ADD" } else { "AND" };
+            if (instr >> 5) & 1 == 1 {
+                format!("{} R{}, R{}, #{}", name, dr, sr1, imm(5))
+            } else {
+                format!("{} R{}, R{}, R{}", name, dr, sr1, instr & 0x7)
+            }
+        }
+        OpCodes::OP_LD => format!("LD R{}, {}", dr, target(9)),
+        OpCodes::OP_ST => format!("ST R{}, {}", dr, target(9)),
+        OpCodes::OP_JSR => {
+            if (instr >> 11) & 1 == 1 {
+                format!("JSR {}", target(11))
+            } else {
+                format!("JSRR R{}", sr1)
+            }
+        }
+        OpCodes::OP_LDR => format!("LDR R{}, R{}, #{}", dr, sr1, imm(6)),
+        OpCodes::OP_STR => format!("STR R{}, R{}, #{}", dr, sr1, imm(6)),
+        OpCodes::OP_RTI => String::from("RTI"),
+        OpCodes::OP_NOT => format!("NOT R{}, R{}", dr, sr1),
+        OpCodes::OP_LDI => format!("LDI R{}, {}", dr, target(9)),
+        OpCodes::OP_STI => format!("STI R{}, {}", dr, target(9)),
+        // RET就是JMP R7，按程序员写的样子显示
+        OpCodes::OP_JMP if sr1 == 7 => String::from("RET"),
+        OpCodes::OP_JMP => format!("JMP R{}", sr1),
+        OpCodes::OP_LEA => format!("LEA R{}, {}", dr, target(9)),
+        OpCodes::OP_TRAP => match trap_name(instr & 0xFF) {
+            Some(name) => String::from(name),
+            None => format!("TRAP {:#04x}", instr & 0xFF),
+        },
+        OpCodes::OP_RES => String::from("RES (illegal)"),
+    }
+}
+
+/// 把一段内存反汇编成清单，每行是地址、原始指令字和助记符。
+/// 数据字也会被当成指令解码，结果没有意义，但地址和十六进制两列总是对的。
+/// 有符号表时，有label的地址前面单独一行写上label
+/// range和read_image返回的加载范围一样，可以一直到0x10000
+pub fn listing(memory: &[u16], range: Range<u32>, symbols: Option<&SymbolTable>) -> String {
+    let mut out = String::new();
+    for addr in range.map(|addr| addr as u16) {
+        if let Some(label) = symbols.and_then(|symbols| symbols.label_at(addr)) {
+            out.push_str(&format!("{}:\n", label));
+        }
+        let instr = memory[addr as usize];
+        out.push_str(&format!(
+            "{:#06x}: {:#06x}  {}\n",
+            addr,
+            instr,
+            disassemble_with(instr, addr, symbols)
+        ));
+    }
+    out
+}
+
+/// trap vector对应的别名
+pub fn trap_name(vector: u16) -> Option<&'static str> {
+    TrapCode::try_from(vector)
+        .ok()
+        .filter(|trap| !trap.is_extension())
+        .map(TrapCode::name)
+}
+
+/// --extensions才认的扩展trap的名字。反汇编不用它，免得把程序自己用的vector显示错
+pub fn extension_trap_name(vector: u16) -> Option<&'static str> {
+    TrapCode::try_from(vector)
+        .ok()
+        .filter(|trap| trap.is_extension())
+        .map(TrapCode::name)
+}
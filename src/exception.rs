@@ -0,0 +1,29 @@
+//! 异常：用户模式下执行RTI(privilege mode violation)和保留的opcode `1101`(illegal opcode)。
+//! 默认(--halt-on-exception)直接报错停机；--trap-exceptions时像真正的LC-3一样
+//! 查0x0100开始的向量表，把控制交给装载进来的操作系统里的异常处理例程，例程用RTI返回。
+
+use crate::Vm;
+
+/// 中断/异常向量表的起始地址，vector x的例程地址存在0x0100 + x
+pub const EXCEPTION_TABLE: u16 = 0x0100;
+
+/// privilege mode violation(用户模式下执行RTI)的异常向量
+pub const PRIVILEGE_VECTOR: u16 = 0x00;
+
+/// illegal opcode(保留的opcode 1101)的异常向量
+pub const ILLEGAL_OPCODE_VECTOR: u16 = 0x01;
+
+impl Vm {
+    /// 把异常交给向量表里的例程，进入的过程和中断一样(见Vm::enter_interrupt)，
+    /// 只是优先级保持不变。压栈的PC已经指向下一条指令，例程用RTI返回时跳过出错的那条。
+    /// 向量表里是0(没有装例程)时什么也不做，返回false，由调用方报错中止
+    pub fn raise_exception(&mut self, vector: u16) -> bool {
+        let routine = self.mem_read(EXCEPTION_TABLE + vector);
+        if routine == 0 {
+            return false;
+        }
+
+        self.enter_interrupt(routine, self.priority());
+        true
+    }
+}
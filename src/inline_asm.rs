@@ -0,0 +1,340 @@
+//! 单行汇编的最小解析器，给测试、REPL、单条指令编码这类场景用。
+//! 只认识核心opcode、trap别名和.FILL，偏移量一律写数字，label由asm模块先换算好。
+//! 分词(去注释、拆操作数)的逻辑单独暴露出来，完整的汇编器也可以直接复用。
+
+use std::fmt;
+
+/// 一个操作数：寄存器、立即数(#10 / x1F / 0x1F)或者label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Reg(u16),
+    Imm(i32),
+    Label(String),
+}
+
+/// 解析出来的一行语句
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+    Instr {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Fill(Operand),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineError {
+    UnknownMnemonic(String),
+    BadOperand(String),
+    OperandCount {
+        mnemonic: String,
+        expected: usize,
+        found: usize,
+    },
+    OutOfRange {
+        value: i32,
+        bits: u16,
+    },
+    UnresolvedLabel(String),
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineError::UnknownMnemonic(m) => write!(f, "unknown mnemonic '{}'", m),
+            LineError::BadOperand(t) => write!(f, "bad operand '{}'", t),
+            LineError::OperandCount {
+                mnemonic,
+                expected,
+                found,
+            } => write!(
+                f,
+                "'{}' expects {} operand(s), found {}",
+                mnemonic, expected, found
+            ),
+            LineError::OutOfRange { value, bits } => {
+                write!(f, "value {} does not fit in {} bits", value, bits)
+            }
+            LineError::UnresolvedLabel(l) => write!(f, "unresolved label '{}'", l),
+        }
+    }
+}
+
+impl std::error::Error for LineError {}
+
+/// 去掉`;`开始的注释
+pub fn strip_comment(line: &str) -> &str {
+    match line.find(';') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+/// 去注释后按空白和逗号拆分成token
+pub fn tokenize(line: &str) -> Vec<&str> {
+    strip_comment(line)
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// 解析数字字面量：#十进制、x/0x十六进制、b二进制，也接受不带前缀的十进制
+pub fn parse_number(tok: &str) -> Option<i32> {
+    if let Some(dec) = tok.strip_prefix('#') {
+        return dec.parse().ok();
+    }
+
+    let (neg, body) = match tok.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, tok),
+    };
+
+    let value = if let Some(hex) = body
+        .strip_prefix("0x")
+        .or_else(|| body.strip_prefix("0X"))
+        .or_else(|| body.strip_prefix('x'))
+        .or_else(|| body.strip_prefix('X'))
+    {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = body.strip_prefix('b').or_else(|| body.strip_prefix('B')) {
+        i32::from_str_radix(bin, 2).ok()?
+    } else if body.starts_with(|c: char| c.is_ascii_digit()) {
+        body.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if neg { -value } else { value })
+}
+
+/// 认识的助记符，BR的各种条件组合单独判断
+const MNEMONICS: [&str; 23] = [
+    "NOP", "ADD", "AND", "NOT", "LD", "LDI", "LDR", "LEA", "ST", "STI", "STR", "JMP", "RET", "JSR",
+    "JSRR", "RTI", "TRAP", "GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT",
+];
+
+/// tok是不是助记符或者伪指令(不区分大小写)，完整的汇编器用它区分行首的label
+pub fn is_mnemonic(tok: &str) -> bool {
+    let upper = tok.to_ascii_uppercase();
+    if upper.starts_with('.') {
+        return true;
+    }
+    if let Some(flags) = upper.strip_prefix("BR") {
+        return flags.chars().all(|c| matches!(c, 'N' | 'Z' | 'P'));
+    }
+    MNEMONICS.contains(&upper.as_str())
+}
+
+/// 解析单个操作数
+pub fn parse_operand(tok: &str) -> Result<Operand, LineError> {
+    let bytes = tok.as_bytes();
+    if bytes.len() == 2
+        && (bytes[0] == b'R' || bytes[0] == b'r')
+        && (b'0'..=b'7').contains(&bytes[1])
+    {
+        return Ok(Operand::Reg((bytes[1] - b'0') as u16));
+    }
+
+    if let Some(n) = parse_number(tok) {
+        return Ok(Operand::Imm(n));
+    }
+
+    if tok.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && tok.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Ok(Operand::Label(tok.to_string()));
+    }
+
+    Err(LineError::BadOperand(tok.to_string()))
+}
+
+/// 解析一行，空行或者只有注释时返回None
+pub fn parse_line(line: &str) -> Result<Option<Stmt>, LineError> {
+    let tokens = tokenize(line);
+    let (first, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return Ok(None),
+    };
+
+    let operands = rest
+        .iter()
+        .map(|t| parse_operand(t))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if first.eq_ignore_ascii_case(".FILL") {
+        return match operands.as_slice() {
+            [op] => Ok(Some(Stmt::Fill(op.clone()))),
+            _ => Err(LineError::OperandCount {
+                mnemonic: ".FILL".to_string(),
+                expected: 1,
+                found: operands.len(),
+            }),
+        };
+    }
+
+    Ok(Some(Stmt::Instr {
+        mnemonic: first.to_ascii_uppercase(),
+        operands,
+    }))
+}
+
+/// 把一条语句编码成16位字
+pub fn encode(stmt: &Stmt) -> Result<u16, LineError> {
+    let (mnemonic, ops) = match stmt {
+        Stmt::Fill(op) => {
+            let v = imm(op)?;
+            if !(-0x8000..=0xFFFF).contains(&v) {
+                return Err(LineError::OutOfRange { value: v, bits: 16 });
+            }
+            return Ok(v as u16);
+        }
+        Stmt::Instr { mnemonic, operands } => (mnemonic.as_str(), operands.as_slice()),
+    };
+
+    let expect = |n: usize| {
+        if ops.len() == n {
+            Ok(())
+        } else {
+            Err(LineError::OperandCount {
+                mnemonic: mnemonic.to_string(),
+                expected: n,
+                found: ops.len(),
+            })
+        }
+    };
+
+    // trap别名
+    let trap_vector = match mnemonic {
+        "GETC" => Some(0x20),
+        "OUT" => Some(0x21),
+        "PUTS" => Some(0x22),
+        "IN" => Some(0x23),
+        "PUTSP" => Some(0x24),
+        "HALT" => Some(0x25),
+        _ => None,
+    };
+    if let Some(vector) = trap_vector {
+        expect(0)?;
+        return Ok(0xF000 | vector);
+    }
+
+    if let Some(flags) = mnemonic.strip_prefix("BR") {
+        let mut nzp = 0;
+        for c in flags.chars() {
+            nzp |= match c {
+                'N' => 0b100,
+                'Z' => 0b010,
+                'P' => 0b001,
+                _ => return Err(LineError::UnknownMnemonic(mnemonic.to_string())),
+            };
+        }
+        // 单独的BR等价于BRnzp
+        if nzp == 0 {
+            nzp = 0b111;
+        }
+        expect(1)?;
+        return Ok(nzp << 9 | signed(&ops[0], 9)?);
+    }
+
+    match mnemonic {
+        "ADD" | "AND" => {
+            expect(3)?;
+            let base = if mnemonic == "ADD" { 0x1000 } else { 0x5000 };
+            let word = base | reg(&ops[0])? << 9 | reg(&ops[1])? << 6;
+            match ops[2] {
+                Operand::Reg(r) => Ok(word | r),
+                _ => Ok(word | 1 << 5 | signed(&ops[2], 5)?),
+            }
+        }
+        "NOT" => {
+            expect(2)?;
+            Ok(0x9000 | reg(&ops[0])? << 9 | reg(&ops[1])? << 6 | 0x3F)
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            expect(2)?;
+            let base = match mnemonic {
+                "LD" => 0x2000,
+                "LDI" => 0xA000,
+                "LEA" => 0xE000,
+                "ST" => 0x3000,
+                _ => 0xB000,
+            };
+            Ok(base | reg(&ops[0])? << 9 | signed(&ops[1], 9)?)
+        }
+        "LDR" | "STR" => {
+            expect(3)?;
+            let base = if mnemonic == "LDR" { 0x6000 } else { 0x7000 };
+            Ok(base | reg(&ops[0])? << 9 | reg(&ops[1])? << 6 | signed(&ops[2], 6)?)
+        }
+        "JMP" => {
+            expect(1)?;
+            Ok(0xC000 | reg(&ops[0])? << 6)
+        }
+        // RET就是JMP R7
+        "RET" => {
+            expect(0)?;
+            Ok(0xC000 | 7 << 6)
+        }
+        "JSR" => {
+            expect(1)?;
+            Ok(0x4800 | signed(&ops[0], 11)?)
+        }
+        "JSRR" => {
+            expect(1)?;
+            Ok(0x4000 | reg(&ops[0])? << 6)
+        }
+        "RTI" => {
+            expect(0)?;
+            Ok(0x8000)
+        }
+        // nzp都是0的BR，反汇编也显示成NOP
+        "NOP" => {
+            expect(0)?;
+            Ok(0x0000)
+        }
+        "TRAP" => {
+            expect(1)?;
+            let v = imm(&ops[0])?;
+            if !(0..=0xFF).contains(&v) {
+                return Err(LineError::OutOfRange { value: v, bits: 8 });
+            }
+            Ok(0xF000 | v as u16)
+        }
+        _ => Err(LineError::UnknownMnemonic(mnemonic.to_string())),
+    }
+}
+
+/// 解析并编码一行，空行或者只有注释时返回None
+pub fn assemble_line(line: &str) -> Result<Option<u16>, LineError> {
+    match parse_line(line)? {
+        Some(stmt) => encode(&stmt).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn reg(op: &Operand) -> Result<u16, LineError> {
+    match op {
+        Operand::Reg(r) => Ok(*r),
+        Operand::Imm(v) => Err(LineError::BadOperand(v.to_string())),
+        Operand::Label(l) => Err(LineError::BadOperand(l.clone())),
+    }
+}
+
+fn imm(op: &Operand) -> Result<i32, LineError> {
+    match op {
+        Operand::Imm(v) => Ok(*v),
+        Operand::Reg(r) => Err(LineError::BadOperand(format!("R{}", r))),
+        Operand::Label(l) => Err(LineError::UnresolvedLabel(l.clone())),
+    }
+}
+
+/// 检查立即数能否放进bits位的有符号字段，返回截断后的字段值
+fn signed(op: &Operand, bits: u16) -> Result<u16, LineError> {
+    let v = imm(op)?;
+    let min = -(1 << (bits - 1));
+    let max = (1 << (bits - 1)) - 1;
+    if v < min || v > max {
+        return Err(LineError::OutOfRange { value: v, bits });
+    }
+    Ok(v as u16 & ((1 << bits) - 1))
+}
@@ -0,0 +1,75 @@
+//! 键盘中断。
+//! KBSR的第14位是中断允许位(IE)。IE置上、键盘上有键、而且当前优先级低于键盘的PL4时，
+//! 在取下一条指令之前进入中断：切换到特权栈，依次压入PSR和PC，
+//! 优先级提到PL4，再跳到中断向量表(0x0100-0x01FF)里键盘的入口0x0180。
+//! 中断服务例程读KBDR取走键之后用RTI返回，op_rti按相反的顺序恢复。
+//! 异常(见exception模块)和中断共用同一张向量表和同一套进入过程。
+
+use crate::exception::EXCEPTION_TABLE;
+use crate::register::Reg;
+use crate::{MemMapReg, Vm, PSR_USER};
+
+/// KBSR的中断允许位
+pub const KBSR_IE: u16 = 1 << 14;
+
+/// 键盘的中断向量
+pub const KEYBOARD_VECTOR: u16 = 0x80;
+
+/// 键盘中断的优先级
+pub const KEYBOARD_PRIORITY: u16 = 4;
+
+/// PSR[10:8]是优先级
+const PSR_PRIORITY_MASK: u16 = 0x0700;
+
+impl Vm {
+    /// 当前的优先级(PSR[10:8])
+    pub fn priority(&self) -> u16 {
+        (self.reg[Reg::PSR] & PSR_PRIORITY_MASK) >> 8
+    }
+
+    /// 取指之前检查有没有要响应的中断，有就完成进入中断的转换，返回true。
+    /// IE没有置上时什么也不做，不会去poll键盘
+    pub fn check_interrupts(&mut self) -> bool {
+        let kbsr = MemMapReg::MR_KBSR as usize;
+        let enabled = self.memory.get(kbsr).is_some_and(|&v| v & KBSR_IE != 0);
+        if !enabled || self.priority() >= KEYBOARD_PRIORITY {
+            return false;
+        }
+
+        if self.key_buffer.is_none() {
+            self.key_buffer = self.poll_key();
+        }
+        if self.key_buffer.is_none() {
+            return false;
+        }
+
+        // 向量表里是0说明没有装中断服务例程，和--use-os对trap向量表的处理一样
+        let entry = EXCEPTION_TABLE + KEYBOARD_VECTOR;
+        let routine = self.mem_read(entry);
+        if routine == 0 {
+            return false;
+        }
+
+        self.enter_interrupt(routine, KEYBOARD_PRIORITY);
+        true
+    }
+
+    /// 保存PSR和PC，切到特权模式和给定的优先级，跳到routine。
+    /// 用户模式下先把R6换成特权栈；压栈的PC是下一条要执行的指令
+    pub(crate) fn enter_interrupt(&mut self, routine: u16, priority: u16) {
+        let psr = self.reg[Reg::PSR];
+        if psr & PSR_USER != 0 {
+            self.saved_usp = self.reg[Reg::R6];
+            self.reg[Reg::R6] = self.saved_ssp;
+        }
+
+        // op_rti先弹出PC再弹出PSR，所以PSR先压
+        let sp = self.reg[Reg::R6].wrapping_sub(2);
+        self.mem_write(sp.wrapping_add(1), psr);
+        self.mem_write(sp, self.reg[Reg::PC]);
+        self.reg[Reg::R6] = sp;
+
+        self.reg[Reg::PSR] = (psr & !(PSR_USER | PSR_PRIORITY_MASK)) | priority << 8;
+        self.reg[Reg::PC] = routine;
+    }
+}
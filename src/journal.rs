@@ -0,0 +1,161 @@
+//! 调试器倒退执行用的日志：每执行一条指令记一条撤销记录，
+//! 包括执行前的寄存器和这条指令写过的每个内存字的旧值，back命令按相反顺序恢复。
+//! 寄存器表很小，直接在Vm::step_once开头整个存一份，不用改每条指令；
+//! 内存只有Vm::mem_write一个写入口，在那里记旧值。
+//! 最多保留depth条记录，更早的自动丢掉。已经输出的字符和读走的键没法撤销。
+//! 在断点处停下来之后执行的那条指令会打上标记，rb命令据此一直倒退到上一次命中断点的地方。
+
+use crate::register::Registers;
+use crate::Vm;
+use std::collections::VecDeque;
+
+/// 一条指令的撤销记录
+#[derive(Debug, Clone)]
+pub struct UndoRecord {
+    /// 执行之前的寄存器表，包括PC和PSR
+    pub reg: Registers,
+    pub saved_usp: u16,
+    pub saved_ssp: u16,
+    pub running: bool,
+    /// 这条指令写过的(地址, 旧值)，按写入顺序
+    pub writes: Vec<(u16, u16)>,
+    /// 执行之前的状态命中了断点
+    pub breakpoint: bool,
+}
+
+/// 有上限的撤销记录环形缓冲
+#[derive(Debug, Clone)]
+pub struct Journal {
+    depth: usize,
+    records: VecDeque<UndoRecord>,
+    /// 当前状态命中了断点，下一条记录要打上标记
+    at_breakpoint: bool,
+}
+
+impl Journal {
+    /// 最多记住depth条指令
+    pub fn new(depth: usize) -> Journal {
+        Journal {
+            depth,
+            records: VecDeque::with_capacity(depth),
+            at_breakpoint: false,
+        }
+    }
+
+    /// 现在可以倒退多少步
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// 丢掉所有记录，比如整个机器状态被换掉之后
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.at_breakpoint = false;
+    }
+
+    /// 当前状态命中了断点，调试器在断点处停下来时调用
+    pub fn mark_breakpoint(&mut self) {
+        self.at_breakpoint = true;
+    }
+
+    /// 记录里有没有命中断点的状态，也就是rb能不能退回去
+    pub fn has_breakpoint(&self) -> bool {
+        self.records.iter().any(|record| record.breakpoint)
+    }
+
+    /// 开始记录新的一条指令，满了就丢掉最早的一条
+    fn begin(&mut self, mut record: UndoRecord) {
+        record.breakpoint = std::mem::take(&mut self.at_breakpoint);
+        if self.depth == 0 {
+            return;
+        }
+        if self.records.len() == self.depth {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// 记下当前指令对addr的一次写入
+    fn record_write(&mut self, addr: u16, old: u16) {
+        if let Some(record) = self.records.back_mut() {
+            record.writes.push((addr, old));
+        }
+    }
+
+    /// 退回去之后的状态就是这条记录执行之前的状态，命中断点的标记也跟着恢复
+    fn pop(&mut self) -> Option<UndoRecord> {
+        let record = self.records.pop_back()?;
+        self.at_breakpoint = record.breakpoint;
+        Some(record)
+    }
+}
+
+impl Vm {
+    /// step_once开头调用：记下执行之前的状态
+    pub(crate) fn journal_begin(&mut self) {
+        let record = UndoRecord {
+            reg: self.reg,
+            saved_usp: self.saved_usp,
+            saved_ssp: self.saved_ssp,
+            running: self.running,
+            writes: vec![],
+            breakpoint: false,
+        };
+        if let Some(journal) = self.journal.as_mut() {
+            journal.begin(record);
+        }
+    }
+
+    /// mem_write真正写入之前调用：记下旧值
+    pub(crate) fn journal_write(&mut self, addr: u16) {
+        // 不在memory里的设备寄存器没有存值，也就没有什么要恢复的
+        let Some(&old) = self.memory.get(addr as usize) else {
+            return;
+        };
+        if let Some(journal) = self.journal.as_mut() {
+            journal.record_write(addr, old);
+        }
+    }
+
+    /// 撤销最近执行的一条指令，没有开启日志或者已经退到头时返回false
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.journal.as_mut().and_then(|journal| journal.pop()) else {
+            return false;
+        };
+
+        for &(addr, old) in record.writes.iter().rev() {
+            self.memory[addr as usize] = old;
+        }
+        self.reg = record.reg;
+        self.saved_usp = record.saved_usp;
+        self.saved_ssp = record.saved_ssp;
+        self.running = record.running;
+        true
+    }
+
+    /// 一直倒退到最近一次命中断点时的状态，返回倒退了多少条指令。
+    /// 没有开启日志，或者保留的记录里没有命中过断点时什么都不做，返回None
+    pub fn rewind_to_breakpoint(&mut self) -> Option<usize> {
+        if !self.journal.as_ref()?.has_breakpoint() {
+            return None;
+        }
+
+        // 记录里有标记，倒退到pop出带标记的那一条就停
+        let mut steps = 0;
+        while self.step_back() {
+            steps += 1;
+            if self
+                .journal
+                .as_ref()
+                .is_some_and(|journal| journal.at_breakpoint)
+            {
+                break;
+            }
+        }
+        Some(steps)
+    }
+}
@@ -0,0 +1,105 @@
+//! 键盘输入。
+//! 程序轮询KBSR时只是想知道"现在有没有按键"，不能因此卡住等用户输入，
+//! 所以这里用poll(2)检查stdin是否可读，只有真的有数据时才去读。
+//! 读的时候绕过std的Stdin缓冲直接调read(2)：Stdin一次可能读进来很多字节放在自己的缓冲区里，
+//! 这些字节poll是看不到的，混用就会出现"明明有输入KBSR却一直没准备好"的情况。
+//! Windows下对应的是等待stdin句柄(超时为0)和ReadFile。
+
+use std::io::{self, Read};
+
+/// 不带缓冲的stdin，每次read都直接读fd 0
+pub struct RawStdin;
+
+#[cfg(unix)]
+impl Read for RawStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::read(0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Read for RawStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use windows_sys::Win32::Storage::FileSystem::ReadFile;
+        use windows_sys::Win32::System::Console::{GetStdHandle, STD_INPUT_HANDLE};
+
+        let mut n = 0u32;
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let ok = unsafe {
+            ReadFile(
+                GetStdHandle(STD_INPUT_HANDLE),
+                buf.as_mut_ptr(),
+                len,
+                &mut n,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+/// stdin上是否已经有可以读的数据，立即返回，不会阻塞
+#[cfg(unix)]
+pub fn key_available() -> bool {
+    let mut fds = libc::pollfd {
+        fd: 0,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, 0) > 0 }
+}
+
+/// stdin上是否已经有可以读的数据，立即返回，不会阻塞
+#[cfg(windows)]
+pub fn key_available() -> bool {
+    use windows_sys::Win32::Foundation::WAIT_OBJECT_0;
+    use windows_sys::Win32::System::Console::{GetStdHandle, STD_INPUT_HANDLE};
+    use windows_sys::Win32::System::Threading::WaitForSingleObject;
+
+    unsafe { WaitForSingleObject(GetStdHandle(STD_INPUT_HANDLE), 0) == WAIT_OBJECT_0 }
+}
+
+/// 阻塞直接从stdin读一个字节，不经过Vm的输入来源，断点提示等按键时用。
+/// 输入已经结束时返回None
+pub fn read_byte() -> io::Result<Option<u8>> {
+    let mut buffer = [0u8; 1];
+    loop {
+        match RawStdin.read(&mut buffer) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(buffer[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// 阻塞从stdin读一行(去掉结尾的换行)，输入已经结束时返回None。
+/// 和read_byte一样一个字节一个字节地直接读fd，行后面的字节留在stdin里，
+/// 调试器的命令和程序自己要读的键共用stdin时谁也不会吞掉对方的输入
+pub fn read_line() -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        match RawStdin.read(&mut byte) {
+            Ok(0) if line.is_empty() => return Ok(None),
+            Ok(0) => break,
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) => line.push(byte[0]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
@@ -1,4 +1,25 @@
-/// 提供基础结构和utility
+//! 提供基础结构和utility
+
+pub mod asm;
+pub mod breakpoint;
+pub mod builder;
+pub mod capabilities;
+pub mod cycles;
+pub mod device;
+pub mod disasm;
+pub mod exception;
+pub mod inline_asm;
+pub mod interrupt;
+pub mod journal;
+pub mod keyboard;
+pub mod opcode;
+pub mod profile;
+pub mod rng;
+pub mod search;
+pub mod snapshot;
+pub mod state;
+pub mod terminal;
+pub mod trapcode;
 
 pub mod register {
     use std::ops::{Index, IndexMut};
@@ -10,6 +31,7 @@ pub mod register {
     ///  FL_POS = 1 << 0, /* P */
     ///  FL_ZRO = 1 << 1, /* Z */
     ///  FL_NEG = 1 << 2, /* N */
+    /// PSR是Processor Status Register：[15]特权位，[10:8]优先级，[2:0]条件码(和COND保持同步)
     /// COUNT是当前计算机架构里寄存器的总数
     pub enum Reg {
         R0,
@@ -22,30 +44,77 @@ pub mod register {
         R7,
         PC,
         COND,
+        PSR,
         COUNT,
     }
 
-    // 为了每次直接能用枚举替代索引访问Vec里的值实现了Index trait
-    // 这样不用每次都Reg as usize
-    impl<T> Index<Reg> for Vec<T> {
-        type Output = T;
+    // 寄存器表是按Reg::COUNT分配的，这隐含要求COUNT是最后一个变体。
+    // 编译期检查一下，以后新增寄存器必须加在COUNT前面并同步更新这里。
+    const _: () = assert!(Reg::COUNT as usize == Reg::PSR as usize + 1);
+
+    /// 寄存器表：固定长度的数组，不用再为十来个寄存器分配堆内存。
+    /// 可以用Reg索引，也可以用指令里解出来的寄存器编号(usize)索引。
+    /// Index<Reg>只实现在这个类型上，memory[Reg::PC]这种写法编译不过
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Registers([u16; Reg::COUNT as usize]);
+
+    impl Registers {
+        /// 全部清零的寄存器表
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn as_slice(&self) -> &[u16] {
+            &self.0
+        }
+
+        pub fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+
+        pub fn iter(&self) -> std::slice::Iter<'_, u16> {
+            self.0.iter()
+        }
+    }
+
+    impl Index<Reg> for Registers {
+        type Output = u16;
 
         fn index(&self, index: Reg) -> &Self::Output {
-            &self[index as usize]
+            &self.0[index as usize]
         }
     }
 
-    impl<T> IndexMut<Reg> for Vec<T> {
+    impl IndexMut<Reg> for Registers {
         fn index_mut(&mut self, index: Reg) -> &mut Self::Output {
-            &mut self[index as usize]
+            &mut self.0[index as usize]
+        }
+    }
+
+    impl Index<usize> for Registers {
+        type Output = u16;
+
+        fn index(&self, index: usize) -> &Self::Output {
+            &self.0[index]
+        }
+    }
+
+    impl IndexMut<usize> for Registers {
+        fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+            &mut self.0[index]
         }
     }
 }
 
 pub mod opcodes {
-    use std::ops::{Index, IndexMut};
-
+    /// 变体的顺序就是编码，判别值正好是0-15，对它的match会编译成跳转表
     #[allow(non_camel_case_types)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(u16)]
     pub enum OpCodes {
         OP_BR,   // branch
         OP_ADD,  // add
@@ -55,7 +124,7 @@ pub mod opcodes {
         OP_AND,  // bitwise and
         OP_LDR,  // load register
         OP_STR,  // store register
-        OP_RTI,  // unused
+        OP_RTI,  // return from interrupt
         OP_NOT,  // bitwise not
         OP_LDI,  // load indirect
         OP_STI,  // store indirect
@@ -65,21 +134,44 @@ pub mod opcodes {
         OP_TRAP, // execute trap
     }
 
-    impl<T> Index<OpCodes> for Vec<T> {
-        type Output = T;
+    /// 按编码排列，下标就是opcode
+    const ALL: [OpCodes; 16] = [
+        OpCodes::OP_BR,
+        OpCodes::OP_ADD,
+        OpCodes::OP_LD,
+        OpCodes::OP_ST,
+        OpCodes::OP_JSR,
+        OpCodes::OP_AND,
+        OpCodes::OP_LDR,
+        OpCodes::OP_STR,
+        OpCodes::OP_RTI,
+        OpCodes::OP_NOT,
+        OpCodes::OP_LDI,
+        OpCodes::OP_STI,
+        OpCodes::OP_JMP,
+        OpCodes::OP_RES,
+        OpCodes::OP_LEA,
+        OpCodes::OP_TRAP,
+    ];
+
+    /// 4位的opcode(instr >> 12)转成枚举，超过15的值原样作为错误返回
+    impl TryFrom<u16> for OpCodes {
+        type Error = u16;
 
-        fn index(&self, index: OpCodes) -> &Self::Output {
-            &self[index as usize]
+        fn try_from(opcode: u16) -> Result<Self, Self::Error> {
+            ALL.get(opcode as usize).copied().ok_or(opcode)
         }
     }
 
-    impl<T> IndexMut<OpCodes> for Vec<T> {
-        fn index_mut(&mut self, index: OpCodes) -> &mut Self::Output {
-            &mut self[index as usize]
-        }
+    /// 取指令的高4位解码。16种编码都有对应的变体，RES和RTI也不例外，
+    /// 它们能不能执行由调用方决定
+    #[inline]
+    pub fn decode_opcode(instr: u16) -> OpCodes {
+        ALL[(instr >> 12) as usize]
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TrapCode {
     GETC = 0x20,  // 32 - get character from keyboard, not echoed onto the terminal
     OUT = 0x21,   // 33 - output a character
@@ -87,14 +179,136 @@ pub enum TrapCode {
     IN = 0x23,    // 35 - get character from keyboard, echoed onto the terminal
     PUTSP = 0x24, // 36 - output a byte string
     HALT = 0x25,  // 37 - halt the program
+    // 下面是这个虚拟机自己的扩展，不是标准LC-3的trap，只有--extensions时才认
+    READLINE = 0x26, // 38 - read a line into the buffer at R0, R1 = max length / count
+    PUTINT = 0x27,   // 39 - output R0 as a signed decimal integer
+    RAND = 0x28,     // 40 - R0 = next pseudo-random number (xorshift32, see --seed)
 }
 
-/// 条件标志是寄存器里存储的上一次计算完成后的结果标记，只有三种值
-#[allow(non_camel_case_types)]
-pub enum CondFlags {
-    FL_POS = 1 << 0, // Positive
-    FL_ZRO = 1 << 1, // Zero
-    FL_NEG = 1 << 2, // Negative
+impl TrapCode {
+    /// 反汇编和--profile里显示的别名
+    pub fn name(self) -> &'static str {
+        match self {
+            TrapCode::GETC => "GETC",
+            TrapCode::OUT => "OUT",
+            TrapCode::PUTS => "PUTS",
+            TrapCode::IN => "IN",
+            TrapCode::PUTSP => "PUTSP",
+            TrapCode::HALT => "HALT",
+            TrapCode::READLINE => "READLINE",
+            TrapCode::PUTINT => "PUTINT",
+            TrapCode::RAND => "RAND",
+        }
+    }
+
+    /// 是不是只有--extensions时才认的扩展trap
+    pub fn is_extension(self) -> bool {
+        matches!(self, TrapCode::READLINE | TrapCode::PUTINT | TrapCode::RAND)
+    }
+}
+
+/// 没有对应TrapCode的trap vector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownTrap(pub u16);
+
+impl fmt::Display for UnknownTrap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown trap vector {:#04x}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownTrap {}
+
+/// trap vector(指令的低8位)转成TrapCode，扩展trap也会转出来，认不认由调用方决定
+impl TryFrom<u16> for TrapCode {
+    type Error = UnknownTrap;
+
+    fn try_from(vector: u16) -> Result<Self, Self::Error> {
+        match vector {
+            0x20 => Ok(TrapCode::GETC),
+            0x21 => Ok(TrapCode::OUT),
+            0x22 => Ok(TrapCode::PUTS),
+            0x23 => Ok(TrapCode::IN),
+            0x24 => Ok(TrapCode::PUTSP),
+            0x25 => Ok(TrapCode::HALT),
+            0x26 => Ok(TrapCode::READLINE),
+            0x27 => Ok(TrapCode::PUTINT),
+            0x28 => Ok(TrapCode::RAND),
+            _ => Err(UnknownTrap(vector)),
+        }
+    }
+}
+
+/// 条件标志是寄存器里存储的上一次计算完成后的结果标记，只有三种值。
+/// 只用低3位，位置和BR指令里的nzp字段一致，所以可以直接拿来做位测试
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cond(u16);
+
+impl Cond {
+    pub const FL_POS: Cond = Cond(1 << 0); // Positive
+    pub const FL_ZRO: Cond = Cond(1 << 1); // Zero
+    pub const FL_NEG: Cond = Cond(1 << 2); // Negative
+
+    /// 一个结果值对应的条件码
+    pub fn of(val: u16) -> Cond {
+        if val == 0 {
+            Cond::FL_ZRO
+        } else if val >> 15 == 1 {
+            /* a 1 in the left-most bit indicates negative */
+            Cond::FL_NEG
+        } else {
+            Cond::FL_POS
+        }
+    }
+
+    /// 从COND寄存器、PSR或者指令的nzp字段构造，只保留低3位
+    pub const fn from_bits(bits: u16) -> Cond {
+        Cond(bits & 0x7)
+    }
+
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// 是不是正好一个标志位，update_flags设出来的条件码总是这样
+    pub const fn is_single_flag(self) -> bool {
+        self.0.count_ones() == 1
+    }
+
+    /// other里的每一位都置上了
+    pub fn contains(self, other: Cond) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// 和other至少有一位相同，BR就是这样判断要不要跳的
+    pub fn intersects(self, other: Cond) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+/// 把COND寄存器的值转成可读的N/Z/P，值不合法时返回"?"
+pub fn cond_name(cond: u16) -> &'static str {
+    match cond {
+        c if c == Cond::FL_POS.bits() => "P",
+        c if c == Cond::FL_ZRO.bits() => "Z",
+        c if c == Cond::FL_NEG.bits() => "N",
+        _ => "?",
+    }
+}
+
+/// 格式化寄存器表：R0-R7按十六进制和有符号十进制(按i16的补码解释)各打一遍，
+/// 再加上PC和字母形式的COND，调试器和trace共用
+pub fn dump_registers(reg: &Registers) -> String {
+    let mut out = String::new();
+    for (r, &val) in reg.iter().take(8).enumerate() {
+        out.push_str(&format!("R{}: {:#06x} {:>6}\n", r, val, to_signed(val)));
+    }
+    out.push_str(&format!(
+        "PC: {:#06x}  COND: {}\n",
+        reg[Reg::PC],
+        cond_name(reg[Reg::COND])
+    ));
+    out
 }
 
 /// Memory Mapped Registers
@@ -103,15 +317,57 @@ pub enum CondFlags {
 /// 它们通常用于与特殊硬件设备交互。
 /// LC-3 有两个需要实现的内存映射寄存器。它们是键盘状态寄存器（KBSR）和键盘数据寄存器（KBDR）。
 /// 指示KBSR是否按下了某个键，并KBDR标识按下了哪个键。
+/// 显示器同理：DSR表示显示器是否就绪，写入DDR的低8位会被输出到stdout。
 #[allow(non_camel_case_types)]
 pub enum MemMapReg {
     MR_KBSR = 0xFE00, //Keyboard Status Register. 0xFE00 = 65024.
     MR_KBDR = 0xFE02, //Keyboard Data Register. 0xFE02 = 65026.
+    MR_DSR = 0xFE04,  //Display Status Register. 0xFE04 = 65028.
+    MR_DDR = 0xFE06,  //Display Data Register. 0xFE06 = 65030.
+    MR_CLK = 0xFE08,  //Clock. 虚拟机启动以来的毫秒数，超过16位后回绕.
+    MR_MCR = 0xFFFE, //Machine Control Register. 第15位清零时机器停止运行，OS的HALT例程就是这样停机的.
 }
 
-use register::Reg;
-use std::io::Read;
-use std::{fs::File, path::Path};
+use exception::{ILLEGAL_OPCODE_VECTOR, PRIVILEGE_VECTOR};
+/// 设备寄存器所在的区域，从0xFE00一直到内存末尾，这里不会放程序和数据
+pub const DEVICE_PAGE_START: u16 = 0xFE00;
+
+/// addr是不是一个真正接了设备的内存映射寄存器
+pub fn is_device_register(addr: u16) -> bool {
+    addr == MemMapReg::MR_KBSR as u16
+        || addr == MemMapReg::MR_KBDR as u16
+        || addr == MemMapReg::MR_DSR as u16
+        || addr == MemMapReg::MR_DDR as u16
+        || addr == MemMapReg::MR_CLK as u16
+        || addr == MemMapReg::MR_MCR as u16
+}
+
+/// 一次非法的内存访问：超出了可用内存，或者落在设备区里没有设备的地址上
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryFault {
+    pub addr: u16,
+    /// true表示写，false表示读
+    pub write: bool,
+}
+
+/// 一次命中监视点的写入
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub old: u16,
+    pub new: u16,
+    /// 执行这次写入的指令的地址
+    pub pc: u16,
+}
+
+use disasm::disassemble_with;
+use opcodes::{decode_opcode, OpCodes};
+use register::{Reg, Registers};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::ops::{Range, RangeInclusive};
+use std::time::Instant;
+use std::{fmt, fs::File, path::Path};
 
 /// 立即数模式值只有5位，但需要与16位数字相加。要进行加法，需要将这 5 位扩展为 16 位以匹配其他数字。
 /// 对于正数，我们可以简单地在附加位中填充 0。对于负数，这会导致问题。例如，5 位中的 -1 是1 1111。
@@ -126,66 +382,1331 @@ pub fn sign_extend(mut x: u16, bit_count: u16) -> u16 {
     x
 }
 
+/// 把16位字按二进制补码解释成有符号数。
+/// sign_extend负责把窄字段扩展成16位参与运算，显示数值时统一用这个，不要各自再转一遍
+pub fn to_signed(x: u16) -> i16 {
+    x as i16
+}
+
+/// 同时显示十六进制和有符号十进制，例如 0xffff (-1)
+pub fn format_word(x: u16) -> String {
+    format!("{:#06x} ({})", x, to_signed(x))
+}
+
+/// --ascii-only模式下的输出过滤，类似hexdump的替换方式：
+/// 可打印ASCII(0x20-0x7E)以及换行、制表符原样输出，其余字节一律替换成'.'，
+/// 这样程序输出里的控制字符和高位字节就不会把终端搞乱。
+pub fn ascii_only(byte: u8) -> u8 {
+    match byte {
+        0x20..=0x7E | b'\n' | b'\t' => byte,
+        _ => b'.',
+    }
+}
+
 /// 每当将值写入寄存器时，我们都需要更新标志以指示其符号。
-pub fn update_flags(r: usize, reg: &mut Vec<u16>) {
-    let val = reg[r];
-
-    if val == 0 {
-        reg[Reg::COND] = CondFlags::FL_ZRO as u16;
-    } else if val >> 15 == 1 {
-        /* a 1 in the left-most bit indicates negative */
-        reg[Reg::COND] = CondFlags::FL_NEG as u16;
-    } else {
-        reg[Reg::COND] = CondFlags::FL_POS as u16;
+/// 返回设置的条件码
+pub fn update_flags(r: usize, reg: &mut Registers) -> Cond {
+    let flags = Cond::of(reg[r]);
+    set_cond_codes(reg, flags);
+    flags
+}
+
+/// 条件码实际存放在PSR的低3位，只改这3位，特权位和优先级保持不变；
+/// COND寄存器作为它的镜像同步更新，branch等指令仍然读COND
+pub fn set_cond_codes(reg: &mut Registers, flags: Cond) {
+    reg[Reg::PSR] = (reg[Reg::PSR] & !0x7) | flags.bits();
+    reg[Reg::COND] = flags.bits();
+}
+
+/// 处理器当前的特权级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    Supervisor,
+    User,
+}
+
+/// LC-3有65536个内存位置，每个位置能存16bits值，所以一共内存有128KB
+pub const MEMORY_SIZE: usize = 65536;
+
+/// PC默认的起始位置，更低的地址留给了trap routine
+pub const PC_START: u16 = 0x3000;
+
+/// 新建一个全部清零的寄存器表，等同于Registers::new()。
+/// 测试里可以直接拿它调用op_add这类只动寄存器的自由函数。
+pub fn new_registers() -> Registers {
+    Registers::new()
+}
+
+/// PSR的第15位，为1表示用户模式，为0表示特权(supervisor)模式
+pub const PSR_USER: u16 = 1 << 15;
+
+/// 官方LC-3 OS里supervisor栈的初始栈顶
+pub const SSP_START: u16 = 0x3000;
+
+/// 虚拟机的全部状态：内存、寄存器表和是否还在运行
+pub struct Vm {
+    pub memory: Vec<u16>,
+    pub reg: Registers,
+    /// PC的初始值，reset时PC回到这里；--origin会改掉它
+    pub origin: u16,
+    pub running: bool,
+    /// 切换到特权模式时保存的用户栈指针(R6)
+    pub saved_usp: u16,
+    /// 切换到用户模式时保存的特权栈指针(R6)
+    pub saved_ssp: u16,
+    /// 轮询KBSR时取到、还没被KBDR读走的键
+    pub key_buffer: Option<u8>,
+    /// 键盘输入的来源，默认是stdin，用set_input换掉
+    input: Box<dyn Read>,
+    /// input是不是终端的stdin：是的话轮询KBSR前要先poll，不能直接读
+    input_is_stdin: bool,
+    /// --ascii-only：trap输出时把不可打印字节替换成'.'
+    pub ascii_only: bool,
+    /// --trap-exceptions：异常交给向量表里的例程，而不是报错停机
+    pub trap_exceptions: bool,
+    /// --log-input：把GETC/IN读到的字节打印到stderr
+    pub log_input: bool,
+    /// 最近一次取到的指令(地址, 指令字)，step用它报告实际执行的是哪条
+    last_fetch: (u16, u16),
+    /// step执行期间记下的内存写入，不在step里时是None
+    recorded_writes: Option<Vec<MemoryWrite>>,
+    /// map_device挂上来的设备，mem_read/mem_write先查这里再查内存
+    devices: Vec<device::Mapping>,
+    /// --quiet：不打印HALT时的停机提示这类虚拟机自己的状态信息
+    pub quiet: bool,
+    /// 设置后检查每次内存访问：地址不能超过这个大小(字)，设备区只允许访问接了设备的寄存器；
+    /// None时只保证不越过memory本身
+    pub memory_limit: Option<usize>,
+    /// 当前指令里发生的第一次非法访问，由step_once取走并报告
+    pub fault: Option<MemoryFault>,
+    /// 显示器输出先攒在这里，阻塞读键盘之前和HALT时再统一flush到sink
+    pub output: Vec<u8>,
+    /// 输出的去处，默认是stdout。为None时输出一直留在output里由调用方取走，
+    /// run_until_halt就是这样收集输出的
+    pub sink: Option<Box<dyn Write>>,
+    /// --trace：每取到一条指令就把地址、指令字和反汇编写一行到这里
+    pub trace: Option<Box<dyn Write>>,
+    /// --profile：按opcode和trap vector统计执行次数
+    pub profile: Option<profile::Profile>,
+    /// --cycles：按时序模型累计周期数
+    pub cycles: Option<cycles::Cycles>,
+    /// --use-os：TRAP按内存0x0000-0x00FF里的trap向量表跳到加载进来的OS例程
+    pub use_os: bool,
+    /// 虚拟机启动的时间，时钟寄存器从这里开始计时
+    pub started: Instant,
+    /// --trap-overflow：ADD发生有符号溢出时在stderr报告，结果不受影响
+    pub trap_overflow: bool,
+    /// --extensions：认这个虚拟机自己扩展的trap(比如0x26 READLINE)，
+    /// 默认关闭，免得和自己用这些vector的程序冲突
+    pub extensions: bool,
+    /// 扩展trap RAND的随机数发生器，默认用当前时间做种子，--seed可以固定下来
+    pub rng: rng::Rng,
+    /// --endian：read_image/read_image_from_bytes按什么字节顺序读镜像，默认大端序
+    pub endian: Endian,
+    /// 用read_image/read_image_from_bytes加载进来的镜像占用的范围，当作代码区
+    pub code_ranges: Vec<Range<u32>>,
+    /// --warn-smc：写代码区时在stderr报告，写入照样进行
+    pub warn_code_writes: bool,
+    /// --warn-wild-jumps：JMP/JSRR跳到加载的镜像以外时在stderr报告
+    pub warn_wild_jumps: bool,
+    /// 已经报告过的(写入指令地址, 被写地址)，同一处写入只报一次
+    reported_code_writes: HashSet<(u16, u16)>,
+    /// --check-cond：每条指令执行完都检查COND是不是正好一个标志位，不是就panic
+    pub check_cond: bool,
+    /// --watch：写这些地址时记录到watch_hit，由运行循环决定暂停还是只打印
+    pub watchpoints: HashSet<u16>,
+    /// 当前指令里第一次命中监视点的写入，由调用step_once的一方取走
+    pub watch_hit: Option<WatchHit>,
+    /// --warn-r7-clobber：JSR覆盖普通指令写进R7的值时警告
+    pub warn_r7_clobber: bool,
+    /// 最近一条往R7里写值的普通指令的地址，只在warn_r7_clobber时记录
+    pub r7_writer: Option<u16>,
+    /// --journal：每条指令的撤销记录，调试器的back命令靠它倒退
+    pub journal: Option<journal::Journal>,
+    /// --symbols：trace里的目标地址按这张表显示成label
+    pub symbols: Option<asm::SymbolTable>,
+    /// --on-eof：键盘输入读完之后GETC/IN和KBSR轮询怎么办
+    pub on_eof: EofPolicy,
+    /// run和run_until_halt每次最多执行这么多条指令，跑满了返回VmError::InstructionLimit
+    pub max_instructions: Option<u64>,
+    /// 写输出或trace时第一次失败的错误，由step取走报告，不会让虚拟机panic
+    pub io_error: Option<io::Error>,
+}
+
+/// GETC/IN在输入读完时放进R0的值，和C的getchar返回EOF(-1)一样
+pub const EOF_SENTINEL: u16 = 0xFFFF;
+
+/// 键盘输入读完(比如重定向进来的文件读到头)之后的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofPolicy {
+    /// GETC/IN把R0设成EOF_SENTINEL，KBSR一直是没有键，程序自己决定怎么办
+    #[default]
+    Sentinel,
+    /// 在stderr说明之后像HALT一样停机
+    Halt,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::with_memory_size(MEMORY_SIZE)
+    }
+
+    /// 内存只有words个字(最多MEMORY_SIZE)的虚拟机，给小测试和模拟内存受限的目标用。
+    /// 超出内存的地址一律当作没有设备：0xFE00以上的设备寄存器也访问不到，
+    /// 读写都会作为非法访问报告。trap仍然可以输出字符
+    pub fn with_memory_size(words: usize) -> Self {
+        let mut reg = new_registers();
+        reg[Reg::PC] = PC_START;
+        reg[Reg::PSR] = PSR_USER;
+
+        Vm::from_parts(reg, vec![0u16; words.min(MEMORY_SIZE)])
+    }
+
+    /// 用现成的寄存器表和内存组一台虚拟机，其余状态都是默认值。
+    /// 保留下来的自由函数借它转调到Vm的方法上
+    pub(crate) fn from_parts(reg: Registers, memory: Vec<u16>) -> Self {
+        Vm {
+            memory,
+            reg,
+            origin: PC_START,
+            running: true,
+            saved_usp: 0,
+            saved_ssp: SSP_START,
+            key_buffer: None,
+            input: Box::new(keyboard::RawStdin),
+            input_is_stdin: true,
+            ascii_only: false,
+            trap_exceptions: false,
+            log_input: false,
+            last_fetch: (0, 0),
+            recorded_writes: None,
+            devices: Vec::new(),
+            quiet: false,
+            memory_limit: None,
+            fault: None,
+            output: Vec::new(),
+            sink: Some(Box::new(io::stdout())),
+            trace: None,
+            profile: None,
+            cycles: None,
+            use_os: false,
+            started: Instant::now(),
+            on_eof: EofPolicy::default(),
+            trap_overflow: false,
+            check_cond: false,
+            endian: Endian::default(),
+            code_ranges: Vec::new(),
+            warn_code_writes: false,
+            warn_wild_jumps: false,
+            reported_code_writes: HashSet::new(),
+            extensions: false,
+            rng: rng::Rng::from_time(),
+            watchpoints: HashSet::new(),
+            watch_hit: None,
+            journal: None,
+            symbols: None,
+            warn_r7_clobber: false,
+            r7_writer: None,
+            max_instructions: None,
+            io_error: None,
+        }
+    }
+
+    /// 把机器状态恢复到刚创建时的样子，但不重新分配内存：
+    /// 内存清零，寄存器清空，PC回到origin，重新开始计时。
+    /// 输入输出、trace、符号表、监视点和各种选项都保留，profile和周期计数也不清，
+    /// 一个进程里连续跑好几个程序时可以一直累计。还没flush的输出先输出掉
+    pub fn reset(&mut self) {
+        self.flush_output();
+
+        self.memory.fill(0);
+        self.reg = new_registers();
+        self.reg[Reg::PC] = self.origin;
+        self.reg[Reg::PSR] = PSR_USER;
+        self.running = true;
+        self.saved_usp = 0;
+        self.saved_ssp = SSP_START;
+        self.key_buffer = None;
+        self.fault = None;
+        self.watch_hit = None;
+        self.r7_writer = None;
+        self.io_error = None;
+        // 内存清空了，之前加载的代码也就没有了
+        self.code_ranges.clear();
+        self.reported_code_writes.clear();
+        if let Some(journal) = self.journal.as_mut() {
+            journal.clear();
+        }
+        self.started = Instant::now();
+    }
+
+    /// 从PSR[15]读出当前特权级别
+    pub fn psr_privilege(&self) -> Privilege {
+        if self.reg[Reg::PSR] & PSR_USER != 0 {
+            Privilege::User
+        } else {
+            Privilege::Supervisor
+        }
+    }
+
+    pub fn set_cond_codes(&mut self, flags: Cond) {
+        set_cond_codes(&mut self.reg, flags);
+    }
+
+    /// 把镜像加载进自己的内存，返回镜像占用的地址范围，这个范围同时记进code_ranges
+    /// origin为Some时镜像没有文件头，直接放到origin，见read_image_from_bytes
+    pub fn read_image(
+        &mut self,
+        image: &str,
+        origin: Option<u16>,
+    ) -> Result<Range<u32>, ImageError> {
+        let range = read_image_with(image, origin, &mut self.memory, self.endian)?;
+        self.code_ranges.push(range.clone());
+        Ok(range)
+    }
+
+    /// 从字节加载镜像，格式和read_image读的文件一样
+    pub fn read_image_from_bytes(
+        &mut self,
+        data: &[u8],
+        origin: Option<u16>,
+    ) -> Result<Range<u32>, ImageError> {
+        let range = read_image_from_bytes_with(data, origin, &mut self.memory, self.endian)?;
+        self.code_ranges.push(range.clone());
+        Ok(range)
+    }
+
+    /// addr是不是在加载进来的某个镜像里
+    pub fn in_code(&self, addr: u16) -> bool {
+        self.code_ranges
+            .iter()
+            .any(|range| range.contains(&(addr as u32)))
+    }
+
+    /// 按加载顺序列出每个镜像里的(地址, 字)，给外部的分析工具用。
+    /// 直接读memory，不会像mem_read那样去查键盘；程序运行后改过的字看到的是新值
+    pub fn loaded_instructions(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.code_ranges.iter().flat_map(move |range| {
+            range
+                .clone()
+                .map(move |addr| (addr as u16, self.peek(addr as u16)))
+        })
+    }
+
+    /// 把range里的内存按read_image读的格式写出去：先是起始地址，再是每个字，都是大端序，
+    /// 写出来的文件可以原样加载回来。直接读memory，不会触发KBSR的副作用。
+    /// range超出内存时返回InvalidInput
+    pub fn dump_memory(
+        &self,
+        range: RangeInclusive<u16>,
+        mut writer: impl Write,
+    ) -> io::Result<()> {
+        let (start, end) = (*range.start() as usize, *range.end() as usize);
+        if end >= self.memory.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "address {:#06x} is outside memory ({} words)",
+                    end,
+                    self.memory.len()
+                ),
+            ));
+        }
+
+        let mut data = Vec::with_capacity(2 * (end + 2).saturating_sub(start));
+        data.extend_from_slice(&range.start().to_be_bytes());
+        for word in self.memory.get(start..=end).unwrap_or_default() {
+            data.extend_from_slice(&word.to_be_bytes());
+        }
+        writer.write_all(&data)?;
+        writer.flush()
+    }
+
+    /// 给调试器、反汇编这些工具用的读内存：直接返回memory里的值，
+    /// 不检查--memory-limit、不轮询键盘，也不会取走KBDR里的键。超出内存的地址读出0
+    pub fn peek(&self, addr: u16) -> u16 {
+        self.memory.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    /// 因为有Memory Mapped Registers的存在，所以读取内存时要先check是不是读取的KBSR/KBDR
+    /// 是先处理一下值不是直接按addr返回；addr上挂了map_device映射的设备时由设备处理。
+    /// 轮询KBSR不会阻塞：只有键盘上确实有数据时才取一个键放进缓冲区并置上ready位；
+    /// 缓冲区里的键被KBDR读走之前不会再取新键，这样两次轮询之间的按键不会被覆盖丢掉
+    pub fn mem_read(&mut self, addr: u16) -> u16 {
+        // 绝大多数读都落在设备页下面的普通内存里，直接取值，不逐个比较设备寄存器的地址。
+        // map_device可以把设备挂在普通内存上，这样的地址还是要走下面的慢路径
+        if addr < DEVICE_PAGE_START && (addr as usize) < self.memory_size() && !self.is_mapped(addr)
+        {
+            return self.memory[addr as usize];
+        }
+        if !self.check_access(addr, false) {
+            return 0;
+        }
+        if let Some(value) = self.read_mapped(addr) {
+            return value;
+        }
+        if addr >= DEVICE_PAGE_START {
+            self.read_device(addr)
+        } else {
+            self.memory[addr as usize]
+        }
+    }
+
+    /// mem_read里设备页(0xFE00以上)的部分
+    fn read_device(&mut self, addr: u16) -> u16 {
+        if addr == MemMapReg::MR_KBSR as u16 {
+            // 轮询键盘的程序一般是在等用户对已有的输出做出反应
+            self.flush_output();
+            if self.key_buffer.is_none() {
+                self.key_buffer = self.poll_key();
+            }
+
+            // 中断允许位是程序自己写进去的，要保留
+            let ie = self.peek(MemMapReg::MR_KBSR as u16) & interrupt::KBSR_IE;
+            let kbsr = match self.key_buffer {
+                Some(key) => {
+                    self.set_device_register(MemMapReg::MR_KBDR, key as u16);
+                    ie | 1 << 15
+                }
+                None => ie,
+            };
+            self.set_device_register(MemMapReg::MR_KBSR, kbsr);
+            return kbsr;
+        } else if addr == MemMapReg::MR_KBDR as u16 {
+            // 读KBDR就是把键取走，清掉ready位，但不会去读新的键
+            let kbdr = match self.key_buffer.take() {
+                Some(key) => {
+                    if self.log_input {
+                        trapcode::log_input("KBDR", &self.reg, key);
+                    }
+                    self.set_device_register(MemMapReg::MR_KBDR, key as u16);
+                    key as u16
+                }
+                None => self.peek(addr),
+            };
+            let ie = self.peek(MemMapReg::MR_KBSR as u16) & interrupt::KBSR_IE;
+            self.set_device_register(MemMapReg::MR_KBSR, ie);
+            return kbdr;
+        } else if addr == MemMapReg::MR_CLK as u16 {
+            // 时钟只是读出经过的时间，不写回内存，也没有别的副作用
+            return self.started.elapsed().as_millis() as u16;
+        } else if addr == MemMapReg::MR_DSR as u16 {
+            let dsr = if self.display_ready() { 1 << 15 } else { 0 };
+            self.set_device_register(MemMapReg::MR_DSR, dsr);
+            return dsr;
+        }
+
+        // --memory-limit下设备寄存器即使不在memory里(内存比设备页小)也可以访问，读出0
+        self.peek(addr)
+    }
+
+    /// 更新一个设备寄存器在内存里的值。内存可能比设备页小，或者正好截在设备页中间
+    /// (见with_memory_size)，放不下的寄存器就当作不存在，不能直接下标访问
+    fn set_device_register(&mut self, reg: MemMapReg, val: u16) {
+        if let Some(word) = self.memory.get_mut(reg as usize) {
+            *word = val;
+        }
+    }
+
+    /// 写入内存，非法地址的写入会被丢掉；
+    /// addr上挂了map_device映射的设备时由设备处理，不写进memory。
+    /// 写DDR就是往显示器输出一个字符，和读KBSR一样要特殊处理；
+    /// KBSR只有中断允许位可以写，ready位由键盘决定
+    pub fn mem_write(&mut self, addr: u16, val: u16) {
+        if !self.check_access(addr, true) || self.write_mapped(addr, val) {
+            return;
+        }
+        let val = if addr == MemMapReg::MR_KBSR as u16 {
+            val & interrupt::KBSR_IE
+        } else {
+            val
+        };
+
+        if self.watch_hit.is_none() && self.watchpoints.contains(&addr) {
+            // 写内存的都是正在执行的指令，取指之后PC已经加过1了
+            self.watch_hit = Some(WatchHit {
+                addr,
+                old: self.peek(addr),
+                new: val,
+                pc: self.reg[Reg::PC].wrapping_sub(1),
+            });
+        }
+
+        if addr == MemMapReg::MR_DDR as u16 {
+            self.emit_char(val as u8);
+        } else if addr == MemMapReg::MR_MCR as u16 && val & (1 << 15) == 0 {
+            self.flush_output();
+            self.running = false;
+        }
+        if self.journal.is_some() {
+            self.journal_write(addr);
+        }
+        let old = self.peek(addr);
+        if let Some(writes) = self.recorded_writes.as_mut() {
+            writes.push(MemoryWrite {
+                addr,
+                old,
+                new: val,
+            });
+        }
+        if self.warn_code_writes {
+            self.check_code_write(addr, val);
+        }
+        // 和mem_read一样，memory里放不下的设备寄存器写了也没有地方存
+        if let Some(word) = self.memory.get_mut(addr as usize) {
+            *word = val;
+        }
+    }
+
+    /// 显示器是否就绪。输出是同步完成的，所以这个简单模型里显示器永远就绪，
+    /// 轮询DSR的程序不会卡住
+    pub fn display_ready(&self) -> bool {
+        true
+    }
+
+    /// 所有字符输出的唯一出口：OUT/PUTS/PUTSP/IN的提示和程序直接写DDR都走这里，
+    /// 两种写法的输出完全一样。显示器就绪时才输出，开启--ascii-only时先过滤一遍。
+    /// 字节原样交给终端，0x80以上不当作Latin-1字符再编码成UTF-8
+    pub fn emit_char(&mut self, byte: u8) {
+        if !self.display_ready() {
+            return;
+        }
+
+        let byte = if self.ascii_only {
+            ascii_only(byte)
+        } else {
+            byte
+        };
+        // 内存小到没有DDR、或者是自由函数trap_out等借用的没有内存的临时Vm时，字符照样输出
+        if let Some(ddr) = self.memory.get_mut(MemMapReg::MR_DDR as usize) {
+            *ddr = byte as u16;
+        }
+        self.output.push(byte);
+
+        // 一直没有读键盘的程序也不能无限攒着
+        if self.output.len() >= OUTPUT_BUFFER_SIZE {
+            self.flush_output();
+        }
+    }
+
+    /// 把攒着的输出真正写到sink，没有sink时留给调用方取走
+    pub fn flush_output(&mut self) {
+        if let Some(sink) = self.sink.as_mut() {
+            let result = sink.write_all(&self.output).and_then(|_| sink.flush());
+            self.output.clear();
+            if let Err(e) = result {
+                self.record_io_error(e);
+            }
+        }
+    }
+
+    /// 记下第一个写输出或trace失败的错误，留给step报告；后面的错误多半是同一个原因
+    fn record_io_error(&mut self, e: io::Error) {
+        if self.io_error.is_none() {
+            self.io_error = Some(e);
+        }
+    }
+
+    /// 依次输出一串字节
+    pub fn emit_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.emit_char(byte);
+        }
+    }
+
+    /// 当前可以访问的内存大小(字)
+    pub fn memory_size(&self) -> usize {
+        match self.memory_limit {
+            Some(limit) => limit.min(self.memory.len()),
+            None => self.memory.len(),
+        }
+    }
+
+    /// 检查一次访问是否合法，非法时记到fault里留给step_once报告。
+    /// 所有读写都经过这里，访问设备的周期开销也在这里记
+    fn check_access(&mut self, addr: u16, write: bool) -> bool {
+        if addr >= DEVICE_PAGE_START {
+            if let Some(cycles) = self.cycles.as_mut() {
+                cycles.device_access();
+            }
+        }
+
+        let legal = if self.is_mapped(addr) {
+            true
+        } else if self.memory_limit.is_some() && addr >= DEVICE_PAGE_START {
+            is_device_register(addr)
+        } else {
+            (addr as usize) < self.memory_size()
+        };
+
+        if !legal && self.fault.is_none() {
+            self.fault = Some(MemoryFault { addr, write });
+        }
+        legal
+    }
+
+    /// GETC/IN取键：先用轮询KBSR时已经缓冲的键，没有的话再阻塞读
+    /// 阻塞之前先flush，保证提示文字在等待输入时已经显示出来
+    /// 输入已经读完时返回None
+    pub fn read_key(&mut self) -> Option<u8> {
+        if let Some(key) = self.key_buffer.take() {
+            return Some(key);
+        }
+
+        self.flush_output();
+        self.read_byte()
+    }
+
+    /// 换掉键盘输入的来源，比如测试里用Cursor喂一段固定的字节
+    pub fn set_input(&mut self, input: impl Read + 'static) {
+        self.input = Box::new(input);
+        self.input_is_stdin = false;
+        self.key_buffer = None;
+    }
+
+    /// 从输入来源阻塞读一个字节，读完了或者出错时返回None
+    pub fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        self.input.read_exact(&mut byte).ok().map(|_| byte[0])
+    }
+
+    /// 不阻塞地看看有没有键。stdin要先poll一下；
+    /// 换成别的输入来源时数据总是现成的，直接读一个字节，读完了就是没有键
+    pub(crate) fn poll_key(&mut self) -> Option<u8> {
+        if self.input_is_stdin && !keyboard::key_available() {
+            return None;
+        }
+
+        // stdin到了EOF时poll也会说可读，读出来什么都没有
+        let key = self.read_byte();
+        if key.is_none() {
+            self.end_of_input();
+        }
+        key
+    }
+
+    /// 输入读完时按on_eof处理，返回交给程序的值
+    pub fn end_of_input(&mut self) -> u16 {
+        if self.on_eof == EofPolicy::Halt && self.running {
+            self.flush_output();
+            eprintln!("Input exhausted, halting.");
+            self.running = false;
+        }
+        EOF_SENTINEL
+    }
+
+    /// --warn-smc：写进加载的镜像范围时报告。加载器分不清代码和数据，
+    /// 镜像里的变量也会被报告，所以同一条指令写同一个地址只报一次
+    fn check_code_write(&mut self, addr: u16, val: u16) {
+        if !self.in_code(addr) {
+            return;
+        }
+        let pc = self.reg[Reg::PC].wrapping_sub(1);
+        if self.reported_code_writes.insert((pc, addr)) {
+            self.flush_output();
+            eprintln!(
+                "Warning: instruction at {:#06x} writes {:#06x} into loaded code at {:#06x} (was {:#06x})",
+                pc,
+                val,
+                addr,
+                self.peek(addr)
+            );
+        }
+    }
+
+    /// --warn-wild-jumps：JMP(包括RET)和JSRR的目标来自寄存器，把数据当成代码执行时
+    /// 多半会跳到莫名其妙的地方，目标不在任何加载的镜像里就报告。
+    /// 指令已经执行完，PC就是跳转目标；没有加载过镜像时不检查
+    fn check_wild_jump(&mut self, pc: u16, instr: u16) {
+        let register_jump = match decode_opcode(instr) {
+            OpCodes::OP_JMP => true,
+            OpCodes::OP_JSR => instr & (1 << 11) == 0,
+            _ => false,
+        };
+        let target = self.reg[Reg::PC];
+        if register_jump && !self.code_ranges.is_empty() && !self.in_code(target) {
+            self.flush_output();
+            eprintln!("Warning: wild jump from {:#06x} to {:#06x}", pc, target);
+        }
+    }
+
+    /// --check-cond：branch按位与COND，默认COND里最多只有一个标志位，
+    /// 有人直接改坏了COND时branch会悄悄走错，这里尽早把它暴露出来。
+    /// 开机时还没有指令设置过条件码，COND是0，这种情况不算错
+    fn cond_is_valid(&self) -> bool {
+        let cond = self.reg[Reg::COND];
+        cond == 0 || Cond::from_bits(cond).is_single_flag() && cond <= 0x7
+    }
+
+    /// --warn-r7-clobber：记下最近一条往R7里写东西的普通指令，
+    /// JSR要覆盖掉它写进去的非0值时在stderr提醒，调用方可能以为R7会保留下来
+    fn check_r7_clobber(&mut self, pc: u16, instr: u16) {
+        let opcode = decode_opcode(instr);
+        let writes_dr = matches!(
+            opcode,
+            OpCodes::OP_ADD
+                | OpCodes::OP_AND
+                | OpCodes::OP_NOT
+                | OpCodes::OP_LD
+                | OpCodes::OP_LDI
+                | OpCodes::OP_LDR
+                | OpCodes::OP_LEA
+        );
+
+        if writes_dr && (instr >> 9) & 0x7 == 7 {
+            self.r7_writer = Some(pc);
+        } else if opcode == OpCodes::OP_JSR {
+            if let Some(writer) = self.r7_writer.take() {
+                if self.reg[Reg::R7] != 0 {
+                    eprintln!(
+                        "Warning: JSR at {:#06x} overwrites R7 = {:#06x} written by the instruction at {:#06x}",
+                        pc,
+                        self.reg[Reg::R7],
+                        writer
+                    );
+                }
+            }
+        } else if opcode == OpCodes::OP_TRAP {
+            // TRAP例程自己负责保存R7，之后R7里的值就不是这条指令写的了
+            self.r7_writer = None;
+        }
+    }
+
+    /// 执行一条指令，出错时返回VmError而不是StepResult。
+    /// 成功时返回实际执行的指令和它改了哪些寄存器、写了哪些内存，
+    /// trace这类观察者不用再自己解码。只关心能不能继续的话看Executed::halted就行。
+    /// 写输出或者trace失败也在这里作为VmError::Io报告
+    pub fn step(&mut self) -> Result<Executed, VmError> {
+        let before = self.reg;
+        self.recorded_writes = Some(Vec::new());
+        let result = self.advance();
+        let writes = self.recorded_writes.take().unwrap_or_default();
+
+        let halted = !result?;
+        let (pc, instr) = self.last_fetch;
+        Ok(Executed {
+            pc,
+            instr,
+            opcode: decode_opcode(instr),
+            before,
+            after: self.reg,
+            writes,
+            halted,
+        })
+    }
+
+    /// 执行一条指令，不收集效果，run这些循环用。Ok(true)表示还可以继续，Ok(false)表示已经停机
+    fn advance(&mut self) -> Result<bool, VmError> {
+        let result = match self.step_once() {
+            StepResult::Continued => Ok(true),
+            StepResult::Halted => Ok(false),
+            StepResult::BadInstruction { pc, instr } => Err(VmError::bad_instruction(pc, instr)),
+            StepResult::IllegalAccess { pc, fault } => Err(VmError::OutOfBounds { pc, fault }),
+            StepResult::BadCond { pc, instr, cond } => Err(VmError::BadCond { pc, instr, cond }),
+        };
+        match self.io_error.take() {
+            Some(e) => Err(VmError::Io(e)),
+            None => result,
+        }
+    }
+
+    /// 用已经设置好的输入输出一直运行到HALT，VmBuilder配出来的虚拟机直接调用这个。
+    /// 结束时把还没flush的输出写到sink
+    pub fn run(&mut self) -> Result<(), VmError> {
+        self.run_configured(None).map(|_| ())
+    }
+
+    /// 和run一样，但PC到了target(那条指令还没执行)时就先停下来，相当于一次性的断点。
+    /// 至少执行一条指令，所以PC已经在target时会运行到下一次回到这里。
+    /// Ok(true)表示到了target，Ok(false)表示先停机了；
+    /// target永远到不了时和run一样受max_instructions限制，不会一直跑下去
+    pub fn run_until(&mut self, target: u16) -> Result<bool, VmError> {
+        self.run_configured(Some(target))
+    }
+
+    fn run_configured(&mut self, stop_at: Option<u16>) -> Result<bool, VmError> {
+        let result = self.run_loop(stop_at, |_| Ok(()));
+        self.flush_output();
+        match self.io_error.take() {
+            Some(e) if result.is_ok() => Err(VmError::Io(e)),
+            _ => result,
+        }
+    }
+
+    /// 不碰终端的运行方式：键盘输入从input读，字符输出写到output，一直运行到HALT。
+    /// input会先整个读进来，所以可以直接传字节切片；输出也可以收集到Vec<u8>里。
+    /// 测试和嵌入都用这个，运行结束后Vm原来的输入输出设置保持不变
+    pub fn run_until_halt(
+        &mut self,
+        mut input: impl Read,
+        mut output: impl Write,
+    ) -> Result<(), VmError> {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+
+        let saved_input = std::mem::replace(&mut self.input, Box::new(io::Cursor::new(bytes)));
+        let saved_is_stdin = std::mem::replace(&mut self.input_is_stdin, false);
+        let saved_sink = self.sink.take();
+
+        let result = self
+            .run_loop(None, |vm| {
+                output.write_all(&vm.output)?;
+                vm.output.clear();
+                Ok(())
+            })
+            .and_then(|_| output.flush().map_err(VmError::from));
+
+        self.input = saved_input;
+        self.input_is_stdin = saved_is_stdin;
+        self.sink = saved_sink;
+        result
+    }
+
+    /// run、run_until和run_until_halt共用的循环，每一步之后调用after_step处理输出。
+    /// 停机时返回Ok(false)，PC到了stop_at时返回Ok(true)
+    fn run_loop(
+        &mut self,
+        stop_at: Option<u16>,
+        mut after_step: impl FnMut(&mut Self) -> io::Result<()>,
+    ) -> Result<bool, VmError> {
+        let mut executed = 0u64;
+        loop {
+            if Some(executed) == self.max_instructions {
+                return Err(VmError::InstructionLimit {
+                    pc: self.reg[Reg::PC],
+                    limit: executed,
+                });
+            }
+
+            // 出错的这条指令之前已经输出的内容也要交出去
+            let step = self.advance();
+            executed += 1;
+            after_step(self)?;
+            if !step? {
+                return Ok(false);
+            }
+            if Some(self.reg[Reg::PC]) == stop_at {
+                return Ok(true);
+            }
+        }
     }
 }
 
-/// 将 LC-3 程序读入内存，比如obj目录下的文件，
-/// 第一个16位是从内存中开始的地址，后面每16位都是一条指令
-pub fn read_image(image: &str, memory: &mut Vec<u16>) -> bool {
-    let path = Path::new(image);
-    let mut file = File::open(path).expect("No such file exists.");
+/// 输出攒到这么多字节时不等读键盘就先flush一次
+const OUTPUT_BUFFER_SIZE: usize = 8 * 1024;
 
-    let mut data = vec![];
-    file.read_to_end(&mut data).expect("Buffer overflow.");
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    // [[val0, val1], ...]
-    let mut iter = data.chunks(2);
+/// 一条指令对内存的一次写入
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub addr: u16,
+    pub old: u16,
+    pub new: u16,
+}
 
-    // 第一个元素就是程序在内存中开始的地址，一般是0x3000 or 12288
-    let pc = iter.next().unwrap();
+/// Vm::step执行的一条指令和它的效果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executed {
+    /// 指令自己的地址
+    pub pc: u16,
+    pub instr: u16,
+    pub opcode: OpCodes,
+    /// 执行前后的整个寄存器表，包括PC、COND和PSR
+    pub before: Registers,
+    pub after: Registers,
+    /// 按写入顺序排列，写DDR这样的设备寄存器也在里面
+    pub writes: Vec<MemoryWrite>,
+    /// 这条指令执行完机器停了(HALT或者清了MCR)
+    pub halted: bool,
+}
 
-    // data一个是u8，所以需要将两个字节组合成一个u16字，
-    // 因为这就是我们的内存存储数据的方式。 也就是说，我们的内存的字长是16位。
-    let mut pc = ((pc[0] as u16) << 8 | pc[1] as u16) as usize;
+impl Executed {
+    /// 值变了的寄存器：(寄存器下标, 旧值, 新值)，按下标排列
+    pub fn changed_registers(&self) -> impl Iterator<Item = (usize, u16, u16)> + '_ {
+        (0..Reg::COUNT as usize)
+            .filter(|&r| self.before[r] != self.after[r])
+            .map(|r| (r, self.before[r], self.after[r]))
+    }
+}
+
+/// 执行一条指令的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// 正常执行完，可以继续下一条
+    Continued,
+    /// 执行了HALT
+    Halted,
+    /// 无法执行的指令：没有交给异常例程的RES、用户模式下的RTI，或者未知的trap code
+    BadInstruction { pc: u16, instr: u16 },
+    /// 取指或者执行时访问了非法地址，pc是这条指令自己的地址
+    IllegalAccess { pc: u16, fault: MemoryFault },
+    /// --check-cond：指令执行完COND不是正好只有N/Z/P中的一位
+    BadCond { pc: u16, instr: u16, cond: u16 },
+}
 
-    for el in iter {
-        memory[pc] = (el[0] as u16) << 8 | el[1] as u16;
-        pc += 1;
+/// step、run和run_until_halt这些库接口返回的错误。
+/// 库里不会直接退出进程，由调用方(比如main)决定怎么报告、用什么退出码
+#[derive(Debug)]
+pub enum VmError {
+    /// 保留的opcode RES
+    BadOpcode { pc: u16, instr: u16 },
+    /// 没有实现的trap vector
+    BadTrap { pc: u16, vector: u8 },
+    /// 当前模式下不能执行的指令：用户模式下的RTI
+    IllegalInstruction { pc: u16, instr: u16 },
+    /// 取指或者执行时访问了非法地址，和StepResult::IllegalAccess一样
+    OutOfBounds { pc: u16, fault: MemoryFault },
+    /// 和StepResult::BadCond一样
+    BadCond { pc: u16, instr: u16, cond: u16 },
+    /// 执行了max_instructions条指令还没停机，pc是下一条要执行的指令
+    InstructionLimit { pc: u16, limit: u64 },
+    /// 镜像格式不对或者放不进内存
+    ImageFormat(ImageError),
+    /// 读输入、写输出或者写trace失败
+    Io(io::Error),
+}
+
+impl VmError {
+    /// 把StepResult::BadInstruction按指令细分成具体的错误
+    pub fn bad_instruction(pc: u16, instr: u16) -> VmError {
+        match decode_opcode(instr) {
+            OpCodes::OP_RES => VmError::BadOpcode { pc, instr },
+            OpCodes::OP_RTI => VmError::IllegalInstruction { pc, instr },
+            _ => VmError::BadTrap {
+                pc,
+                vector: (instr & 0xFF) as u8,
+            },
+        }
     }
+}
 
-    true
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::BadOpcode { pc, instr } => {
+                write!(f, "bad opcode in {:#06x} at {:#06x}", instr, pc)
+            }
+            VmError::BadTrap { pc, vector } => {
+                write!(f, "invalid trap code {:#04x} at {:#06x}", vector, pc)
+            }
+            VmError::IllegalInstruction { pc, instr } => {
+                write!(f, "privilege mode violation: {:#06x} at {:#06x}", instr, pc)
+            }
+            VmError::OutOfBounds { pc, fault } => write!(
+                f,
+                "illegal memory {} at {:#06x} by instruction at {:#06x}",
+                if fault.write { "write" } else { "read" },
+                fault.addr,
+                pc
+            ),
+            VmError::BadCond { pc, instr, cond } => write!(
+                f,
+                "COND is {:#05b} after {:#06x} at {:#06x}, expected exactly one of N/Z/P",
+                cond, instr, pc
+            ),
+            VmError::InstructionLimit { pc, limit } => {
+                write!(f, "instruction limit of {} reached at {:#06x}", limit, pc)
+            }
+            VmError::ImageFormat(e) => write!(f, "{}", e),
+            VmError::Io(e) => write!(f, "{}", e),
+        }
+    }
 }
 
-/// 因为有Memory Mapped Registers的存在，所以读取内存时要先check是不是读取的KBSR
-/// 是先处理一下值不是直接按addr返回
-pub fn mem_read(addr: u16, memory: &mut Vec<u16>) -> u16 {
-    if addr == MemMapReg::MR_KBSR as u16 {
-        let mut buffer = [0; 1];
-        std::io::stdin().read_exact(&mut buffer).unwrap();
+impl std::error::Error for VmError {}
+
+impl From<io::Error> for VmError {
+    fn from(e: io::Error) -> Self {
+        VmError::Io(e)
+    }
+}
+
+impl From<ImageError> for VmError {
+    fn from(e: ImageError) -> Self {
+        VmError::ImageFormat(e)
+    }
+}
+
+impl Vm {
+    /// 最多执行n条指令，中途停机或出错时返回那一步的结果，n条都正常执行完返回Continued。
+    /// 不flush输出也不看max_instructions，给基准测试和只想跑一段固定长度的嵌入方用
+    pub fn step_n(&mut self, n: u64) -> StepResult {
+        for _ in 0..n {
+            match self.step_once() {
+                StepResult::Continued => {}
+                other => return other,
+            }
+        }
+        StepResult::Continued
+    }
+
+    /// 执行一个完整的取指-执行周期：
+    /// 1.从内存中的寄存器地址加载一条指令PC。
+    /// 2.增加PC寄存器。
+    /// 3.查看操作码以确定它应该执行哪种类型的指令。
+    /// 4.使用指令中的参数执行指令。
+    /// 正常运行的主循环和测试都走这里，保证两边的执行逻辑完全一致
+    pub fn step_once(&mut self) -> StepResult {
+        self.fault = None;
+        self.watch_hit = None;
+        if self.journal.is_some() {
+            self.journal_begin();
+        }
+
+        // 有要响应的中断时先转到中断服务例程，这一步取的就是例程的第一条指令
+        if self.check_interrupts() {
+            if let Some(fault) = self.fault.take() {
+                return StepResult::IllegalAccess {
+                    pc: self.reg[Reg::PC],
+                    fault,
+                };
+            }
+        }
+
+        // 加载一条指令
+        let pc = self.reg[Reg::PC];
+        let instr = self.mem_read(pc);
+        self.last_fetch = (pc, instr);
+        if let Some(fault) = self.fault.take() {
+            return StepResult::IllegalAccess { pc, fault };
+        }
+
+        // 用的是自增之前的PC，trace里的地址就是这条指令自己的地址
+        if let Some(trace) = self.trace.as_mut() {
+            let result = writeln!(
+                trace,
+                "{:#06x}: {:#06x}  {}",
+                pc,
+                instr,
+                disassemble_with(instr, pc, self.symbols.as_ref())
+            );
+            if let Err(e) = result {
+                self.record_io_error(e);
+            }
+        }
+
+        // PC地址+1留待下次继续读取
+        self.reg[Reg::PC] = pc.wrapping_add(1);
+
+        // 获取操作码
+        let opcode = decode_opcode(instr);
+        if let Some(profile) = self.profile.as_mut() {
+            profile.opcodes[opcode as usize] += 1;
+        }
+        if let Some(cycles) = self.cycles.as_mut() {
+            cycles.instruction(opcode as u16);
+        }
+        //println!("Executing Instr {:#018b} and Opcode bit: {}", instr, opcode);
+        if self.warn_r7_clobber {
+            self.check_r7_clobber(pc, instr);
+        }
 
-        if buffer[0] != 0 {
-            memory[MemMapReg::MR_KBSR as usize] = 1 << 15;
-            memory[MemMapReg::MR_KBDR as usize] = buffer[0] as u16;
+        // 开始匹配action。16个变体都列出来、没有通配分支，
+        // 以后加新的opcode处理时编译器会提醒；RES和用户模式的RTI在各自的分支里报错
+        match opcode {
+            OpCodes::OP_ADD => {
+                self.op_add(instr);
+            }
+            OpCodes::OP_AND => {
+                self.op_and(instr);
+            }
+            OpCodes::OP_BR => {
+                self.op_branch(instr);
+            }
+            OpCodes::OP_JMP => {
+                self.op_jump(instr);
+            }
+            OpCodes::OP_JSR => {
+                self.op_jsr(instr);
+            }
+            OpCodes::OP_LD => {
+                self.op_load(instr);
+            }
+            OpCodes::OP_LDI => {
+                self.op_ldi(instr);
+            }
+            OpCodes::OP_LDR => {
+                self.op_ldr(instr);
+            }
+            OpCodes::OP_LEA => {
+                self.op_lea(instr);
+            }
+            OpCodes::OP_NOT => {
+                self.op_not(instr);
+            }
+            OpCodes::OP_ST => {
+                self.op_st(instr);
+            }
+            OpCodes::OP_STI => {
+                self.op_sti(instr);
+            }
+            OpCodes::OP_STR => {
+                self.op_str(instr);
+            }
+            OpCodes::OP_RES => {
+                if !(self.trap_exceptions && self.raise_exception(ILLEGAL_OPCODE_VECTOR)) {
+                    return StepResult::BadInstruction { pc, instr };
+                }
+            }
+            OpCodes::OP_RTI => {
+                // 用户模式下执行RTI是privilege mode violation，没有交给异常处理例程时只能中止
+                if self.psr_privilege() == Privilege::User {
+                    if !(self.trap_exceptions && self.raise_exception(PRIVILEGE_VECTOR)) {
+                        return StepResult::BadInstruction { pc, instr };
+                    }
+                } else {
+                    self.op_rti(instr);
+                }
+            }
+            // 1111就是trap code
+            OpCodes::OP_TRAP => {
+                // 先处理最后8位以获取具体trapcode
+                let trapcode = instr & 0xFF;
+                if let Some(profile) = self.profile.as_mut() {
+                    profile.traps[trapcode as usize] += 1;
+                }
+                // println!("Executing {} TRAP, Instr {:#018b}", trapcode, instr);
+
+                // --use-os时，向量表里有这个trap的入口就交给内存里的OS例程：
+                // 和真正的LC-3一样R7保存返回地址，例程最后用RET返回。
+                // 向量表里是0说明没有加载对应的例程，仍然走下面的原生实现
+                let routine = if self.use_os {
+                    self.mem_read(trapcode)
+                } else {
+                    0
+                };
+
+                // 扩展trap只有--extensions时才认，否则和没有定义的vector一样
+                let trap = TrapCode::try_from(trapcode)
+                    .ok()
+                    .filter(|trap| self.extensions || !trap.is_extension());
+                match trap {
+                    _ if routine != 0 => {
+                        self.reg[Reg::R7] = self.reg[Reg::PC];
+                        self.reg[Reg::PC] = routine;
+                    }
+                    Some(TrapCode::GETC) => self.trap_getc(),
+                    Some(TrapCode::OUT) => self.trap_out(),
+                    Some(TrapCode::PUTS) => self.trap_puts(),
+                    Some(TrapCode::IN) => self.trap_in(),
+                    Some(TrapCode::PUTSP) => self.trap_putsp(),
+                    Some(TrapCode::HALT) => self.trap_halt(),
+                    Some(TrapCode::READLINE) => self.trap_readline(),
+                    Some(TrapCode::PUTINT) => self.trap_putint(),
+                    Some(TrapCode::RAND) => self.trap_rand(),
+                    None => {
+                        return StepResult::BadInstruction { pc, instr };
+                    }
+                }
+            }
+        }
+
+        if let Some(fault) = self.fault.take() {
+            return StepResult::IllegalAccess { pc, fault };
+        }
+
+        if self.check_cond && !self.cond_is_valid() {
+            return StepResult::BadCond {
+                pc,
+                instr,
+                cond: self.reg[Reg::COND],
+            };
+        }
+        if self.warn_wild_jumps {
+            self.check_wild_jump(pc, instr);
+        }
+
+        if self.running {
+            StepResult::Continued
         } else {
-            memory[MemMapReg::MR_KBSR as usize] = 0;
+            StepResult::Halted
+        }
+    }
+}
+
+/// 镜像文件里一个字的两个字节的顺序。标准的.obj是大端序，有些工具链输出小端序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+impl Endian {
+    /// 按这种顺序把文件里相邻的两个字节组合成一个字
+    pub fn word(self, first: u8, second: u8) -> u16 {
+        match self {
+            Endian::Big => u16::from_be_bytes([first, second]),
+            Endian::Little => u16::from_le_bytes([first, second]),
         }
     }
+}
 
-    memory[addr as usize]
+/// 加载镜像失败的原因
+#[derive(Debug)]
+pub enum ImageError {
+    /// 文件不存在
+    NotFound,
+    /// 打开或读取文件时的其它IO错误
+    IoError(std::io::Error),
+    /// 文件不足两个字节，连起始地址都读不出来
+    TruncatedHeader,
+    /// 文件长度是奇数，最后多出一个凑不成字的字节
+    OddLength,
+    /// 从origin开始放不下这么多字，会越过内存末尾
+    TooLarge { origin: u16, words: usize },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::NotFound => write!(f, "no such file"),
+            ImageError::IoError(e) => write!(f, "{}", e),
+            ImageError::TruncatedHeader => write!(f, "file is too short to contain an origin word"),
+            ImageError::OddLength => {
+                write!(f, "file has a trailing byte that is not part of a word")
+            }
+            ImageError::TooLarge { origin, words } => write!(
+                f,
+                "{} words starting at {:#06x} do not fit in memory",
+                words, origin
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<std::io::Error> for ImageError {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            ImageError::NotFound
+        } else {
+            ImageError::IoError(e)
+        }
+    }
+}
+
+/// 将 LC-3 程序读入内存，比如obj目录下的文件，image为"-"时从stdin读，
+/// 读出整个文件之后交给read_image_from_bytes解析，origin的含义也一样
+/// 返回镜像占用的地址范围(origin..end)，镜像可以一直放到0xFFFF，所以end用u32
+pub fn read_image(
+    image: &str,
+    origin: Option<u16>,
+    memory: &mut [u16],
+) -> Result<Range<u32>, ImageError> {
+    read_image_with(image, origin, memory, Endian::Big)
+}
+
+/// 和read_image一样，字节顺序由endian决定
+pub fn read_image_with(
+    image: &str,
+    origin: Option<u16>,
+    memory: &mut [u16],
+    endian: Endian,
+) -> Result<Range<u32>, ImageError> {
+    let mut data = vec![];
+    if image == "-" {
+        // 一直读到EOF，镜像必须完整读完之后stdin才能交给键盘使用
+        io::stdin().read_to_end(&mut data)?;
+    } else {
+        let path = Path::new(image);
+        let mut file = File::open(path)?;
+        file.read_to_end(&mut data)?;
+    }
+
+    read_image_from_bytes_with(&data, origin, memory, endian)
+}
+
+/// 从内存里的字节解析镜像，嵌在程序里的镜像、从网络收到的镜像都可以直接加载。
+/// origin为None时第一个16位是从内存中开始的地址，后面每16位都是一条指令；
+/// 给了origin时整个文件都是没有文件头的代码，直接从origin开始放。都是大端序
+/// 返回镜像占用的地址范围(origin..end)
+pub fn read_image_from_bytes(
+    data: &[u8],
+    origin: Option<u16>,
+    memory: &mut [u16],
+) -> Result<Range<u32>, ImageError> {
+    read_image_from_bytes_with(data, origin, memory, Endian::Big)
+}
+
+/// 和read_image_from_bytes一样，但起始地址和每个字都按endian组合
+pub fn read_image_from_bytes_with(
+    data: &[u8],
+    origin: Option<u16>,
+    memory: &mut [u16],
+    endian: Endian,
+) -> Result<Range<u32>, ImageError> {
+    if origin.is_none() && data.len() < 2 {
+        return Err(ImageError::TruncatedHeader);
+    }
+    if !data.len().is_multiple_of(2) {
+        return Err(ImageError::OddLength);
+    }
+
+    // [[val0, val1], ...]
+    let mut iter = data.chunks(2);
+
+    // data一个是u8，所以需要将两个字节组合成一个u16字，
+    // 因为这就是我们的内存存储数据的方式。 也就是说，我们的内存的字长是16位。
+    let pc = match origin {
+        Some(origin) => origin as usize,
+        // 第一个元素就是程序在内存中开始的地址，一般是0x3000 or 12288
+        None => {
+            let pc = iter.next().unwrap();
+            endian.word(pc[0], pc[1]) as usize
+        }
+    };
+
+    // 最后一个字可以正好落在内存的最后一个地址上，完整内存时是0xFFFF
+    let words = iter.len();
+    if pc + words > memory.len() {
+        return Err(ImageError::TooLarge {
+            origin: pc as u16,
+            words,
+        });
+    }
+
+    for (offset, el) in iter.enumerate() {
+        memory[pc + offset] = endian.word(el[0], el[1]);
+    }
+
+    Ok(pc as u32..(pc + words) as u32)
+}
+
+/// 同Vm::mem_read，不经过map_device映射的设备；轮询到的键直接放进KBDR，不会缓冲
+pub fn mem_read(addr: u16, memory: &mut Vec<u16>) -> u16 {
+    with_vm(&mut new_registers(), memory, |vm| vm.mem_read(addr))
 }
 
 /// 写入内存
-pub fn mem_write(addr: u16, val: u16, memory: &mut Vec<u16>) {
+pub fn mem_write(addr: u16, val: u16, memory: &mut [u16]) {
     memory[addr as usize] = val;
 }
+
+/// 内存映射寄存器和键盘缓冲都在Vm上，保留下来的自由函数借reg和memory组一台临时的虚拟机，
+/// 转调到Vm的方法上
+pub(crate) fn with_vm<T>(
+    reg: &mut Registers,
+    memory: &mut Vec<u16>,
+    op: impl FnOnce(&mut Vm) -> T,
+) -> T {
+    let mut vm = Vm::from_parts(std::mem::take(reg), std::mem::take(memory));
+    let result = op(&mut vm);
+    *reg = vm.reg;
+    *memory = vm.memory;
+    result
+}
+
+/// 两段加载范围重叠的部分，不重叠时返回None。
+/// 加载多个镜像时用来发现后面的镜像覆盖了前面的
+pub fn overlap(a: &Range<u32>, b: &Range<u32>) -> Option<Range<u32>> {
+    let start = a.start.max(b.start);
+    let end = a.end.min(b.end);
+    (start < end).then_some(start..end)
+}
@@ -1,170 +1,922 @@
-extern crate termios;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::ops::{Range, RangeInclusive};
+use std::process::ExitCode;
+use std::{env, fs, io};
 
-use std::{env, process};
-use termios::*;
+use lc_3_vm::asm::SymbolTable;
+use lc_3_vm::breakpoint::{split_spec, Condition};
+use lc_3_vm::capabilities::capabilities;
+use lc_3_vm::cycles::Cycles;
+use lc_3_vm::disasm::{disassemble_with, listing};
+use lc_3_vm::journal::Journal;
+use lc_3_vm::keyboard;
+use lc_3_vm::profile::Profile;
+use lc_3_vm::register::{Reg, Registers};
+use lc_3_vm::rng::Rng;
+use lc_3_vm::search::{find_string, find_words};
+use lc_3_vm::state::MachineState;
+use lc_3_vm::terminal::RawMode;
+use lc_3_vm::{
+    dump_registers, format_word, overlap, Endian, EofPolicy, Vm, VmError, DEVICE_PAGE_START,
+};
 
-use lc_3_vm::opcodes::OpCodes;
-use lc_3_vm::register::Reg;
-use lc_3_vm::{mem_read, read_image, TrapCode};
-
-mod opcode;
-use opcode::*;
-
-mod trapcode;
-use trapcode::*;
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) | Err(code) => ExitCode::from(code),
+    }
+}
 
-fn main() {
+/// 真正的入口。出错时返回退出码而不是直接process::exit，
+/// 这样返回途中RawMode guard会被drop，终端设置一定会恢复；panic时的栈展开也一样。
+/// Ok里是程序自己的退出码(默认0，见--exit-code-from-r0)，Err里是虚拟机出错时的退出码
+fn run() -> Result<u8, u8> {
     // 获取输入参数
     let args = env::args().collect::<Vec<String>>();
-    if args.len() < 2 {
-        println!("Error: 至少提供一个VM镜像地址");
-        println!("Usage: lc-3_vm <image-file1> [image-file2]...");
-        process::exit(2);
+
+    // 以--开头的是选项，其余都当作镜像地址
+    let mut ascii_only = false;
+    let mut trap_exceptions = false;
+    let mut dump_state: Option<&String> = None;
+    let mut dump_state_memory = false;
+    // 地址范围为None时写出设备寄存器区以下的全部内存
+    let mut dump_mem: Option<(&str, Option<RangeInclusive<u16>>)> = None;
+    let mut log_input = false;
+    let mut quiet = false;
+    let mut exit_code_from_r0 = false;
+    let mut livelock_window: Option<u64> = None;
+    let mut max_instructions: Option<u64> = None;
+    let mut watchdog: Option<u64> = None;
+    let mut memory_limit: Option<usize> = None;
+    let mut origin: Option<u16> = None;
+    let mut break_specs: Vec<&str> = vec![];
+    let mut symbols_path: Option<&String> = None;
+    let mut debug = false;
+    let mut disasm = false;
+    let mut profile = false;
+    let mut cycles = false;
+    let mut use_os = false;
+    let mut with_os: Option<&String> = None;
+    let mut on_eof = EofPolicy::default();
+    let mut endian = Endian::default();
+    let mut trap_overflow = false;
+    let mut check_cond = false;
+    let mut extensions = false;
+    let mut seed: Option<u32> = None;
+    let mut warn_r7_clobber = false;
+    let mut warn_smc = false;
+    let mut warn_wild_jumps = false;
+    let mut watchpoints = HashSet::new();
+    let mut no_overlap = false;
+    let mut step_limit: Option<u64> = None;
+    let mut journal: Option<usize> = None;
+    let mut input_script: Option<&String> = None;
+    let mut output_file: Option<&String> = None;
+    // Some(None)表示trace到stderr，Some(Some(path))表示写到文件
+    let mut trace: Option<Option<&str>> = None;
+    let mut images = vec![];
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--version" => {
+                print!("{}", capabilities().report());
+                return Ok(0);
+            }
+            "--ascii-only" => ascii_only = true,
+            "--halt-on-exception" => trap_exceptions = false,
+            "--trap-exceptions" => trap_exceptions = true,
+            "--dump-state-json" => match iter.next() {
+                Some(path) => dump_state = Some(path),
+                None => {
+                    eprintln!("Error: --dump-state-json 需要一个文件路径");
+                    return Err(2);
+                }
+            },
+            "--dump-state-memory" => dump_state_memory = true,
+            "--dump-mem" => {
+                match iter.next() {
+                    Some(spec) => dump_mem = Some(parse_dump_mem(spec)),
+                    None => {
+                        eprintln!("Error: --dump-mem 需要 <file>[@<start>-<end>]，例如 out.obj@0x3000-0x30ff");
+                        return Err(2);
+                    }
+                }
+            }
+            "--log-input" => log_input = true,
+            "--quiet" => quiet = true,
+            "--exit-code-from-r0" => exit_code_from_r0 = true,
+            "--debug" => debug = true,
+            "--disasm" => disasm = true,
+            "--profile" => profile = true,
+            "--cycles" => cycles = true,
+            "--use-os" => use_os = true,
+            "--with-os" => match iter.next() {
+                Some(path) => with_os = Some(path),
+                None => {
+                    eprintln!("Error: --with-os 需要一个OS镜像文件");
+                    return Err(2);
+                }
+            },
+            "--trap-overflow" => trap_overflow = true,
+            "--check-cond" => check_cond = true,
+            "--extensions" => extensions = true,
+            "--seed" => match iter.next().and_then(|n| n.parse::<u32>().ok()) {
+                Some(n) => seed = Some(n),
+                None => {
+                    eprintln!("Error: --seed 需要一个0到4294967295之间的整数");
+                    return Err(2);
+                }
+            },
+            "--warn-r7-clobber" => warn_r7_clobber = true,
+            "--warn-smc" => warn_smc = true,
+            "--warn-wild-jumps" => warn_wild_jumps = true,
+            "--no-overlap" => no_overlap = true,
+            "--step-limit" => match iter.next().and_then(|n| n.parse::<u64>().ok()) {
+                Some(n) => step_limit = Some(n),
+                None => {
+                    eprintln!("Error: --step-limit 需要一个指令数");
+                    return Err(2);
+                }
+            },
+            "--input-script" => match iter.next() {
+                Some(path) => input_script = Some(path),
+                None => {
+                    eprintln!("Error: --input-script 需要一个文件路径");
+                    return Err(2);
+                }
+            },
+            "--output-file" => match iter.next() {
+                Some(path) => output_file = Some(path),
+                None => {
+                    eprintln!("Error: --output-file 需要一个文件路径");
+                    return Err(2);
+                }
+            },
+            "--journal" => match iter.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n > 0 => journal = Some(n),
+                _ => {
+                    eprintln!("Error: --journal 需要一个正整数");
+                    return Err(2);
+                }
+            },
+            "--watch" => match iter.next().and_then(|s| parse_u16(s)) {
+                Some(addr) => {
+                    watchpoints.insert(addr);
+                }
+                None => {
+                    eprintln!("Error: --watch 需要一个地址，例如 0x4000");
+                    return Err(2);
+                }
+            },
+            "--endian" => match iter.next().map(|s| s.as_str()) {
+                Some("big") => endian = Endian::Big,
+                Some("little") => endian = Endian::Little,
+                _ => {
+                    eprintln!("Error: --endian 需要 big 或 little");
+                    return Err(2);
+                }
+            },
+            "--on-eof" => match iter.next().map(|s| s.as_str()) {
+                Some("eof") => on_eof = EofPolicy::Sentinel,
+                Some("halt") => on_eof = EofPolicy::Halt,
+                _ => {
+                    eprintln!("Error: --on-eof 需要 eof 或 halt");
+                    return Err(2);
+                }
+            },
+            "--livelock-window" => match iter.next().and_then(|n| n.parse::<u64>().ok()) {
+                Some(n) if n > 0 => livelock_window = Some(n),
+                _ => {
+                    eprintln!("Error: --livelock-window 需要一个正整数");
+                    return Err(2);
+                }
+            },
+            "--memory-limit" => match iter.next().and_then(|n| parse_u16(n)) {
+                Some(words) if words > 0 => memory_limit = Some(words as usize),
+                _ => {
+                    eprintln!("Error: --memory-limit 需要一个大于0的字数，例如 0x4000");
+                    return Err(2);
+                }
+            },
+            "--watchdog" => match iter.next().and_then(|n| n.parse::<u64>().ok()) {
+                Some(n) if n > 0 => watchdog = Some(n),
+                _ => {
+                    eprintln!("Error: --watchdog 需要一个正整数");
+                    return Err(2);
+                }
+            },
+            "--max-instructions" => match iter.next().and_then(|n| n.parse::<u64>().ok()) {
+                Some(n) => max_instructions = Some(n),
+                None => {
+                    eprintln!("Error: --max-instructions 需要一个非负整数");
+                    return Err(2);
+                }
+            },
+            // label要等符号表读进来之后才能解析
+            "--break" => match iter.next() {
+                Some(spec) => break_specs.push(spec.as_str()),
+                None => {
+                    eprintln!("Error: --break 需要一个16位地址或者label，例如 0x3005");
+                    return Err(2);
+                }
+            },
+            "--symbols" => match iter.next() {
+                Some(path) => symbols_path = Some(path),
+                None => {
+                    eprintln!("Error: --symbols 需要一个文件路径");
+                    return Err(2);
+                }
+            },
+            "--load" => match iter.next().and_then(|spec| parse_load(spec)) {
+                Some(load) => images.push(load),
+                None => {
+                    eprintln!("Error: --load 需要 <file>@<addr>，例如 image.bin@0x3000");
+                    return Err(2);
+                }
+            },
+            "--origin" | "--pc" => match iter.next().and_then(|n| parse_u16(n)) {
+                Some(addr) => origin = Some(addr),
+                None => {
+                    eprintln!("Error: {} 需要一个16位地址，例如 0x3000", arg);
+                    return Err(2);
+                }
+            },
+            "--trace" => trace = Some(None),
+            flag if flag.starts_with("--trace=") => trace = Some(Some(&flag["--trace=".len()..])),
+            flag if flag.starts_with("--") => {
+                eprintln!("Error: 未知选项 {}", flag);
+                return Err(2);
+            }
+            _ => images.push((arg.as_str(), None)),
+        }
+    }
+
+    if images.is_empty() {
+        eprintln!("Error: 至少提供一个VM镜像地址");
+        eprintln!("Usage: lc-3_vm [--version] [--ascii-only] [--log-input] [--quiet] [--exit-code-from-r0] [--origin <addr>] [--load <file>@<addr>]... [--endian <big|little>] [--no-overlap] [--symbols <file>] [--break <addr|label>[ if <reg> <op> <value>]]... [--watch <addr>]... [--debug] [--halt-on-exception | --trap-exceptions] [--journal <n>] [--disasm] [--profile] [--cycles] [--use-os] [--with-os <os-image>] [--input-script <file>] [--output-file <file>] [--on-eof <eof|halt>] [--trap-overflow] [--check-cond] [--extensions] [--seed <n>] [--warn-r7-clobber] [--warn-smc] [--warn-wild-jumps] [--trace[=<file>]] [--livelock-window <n>] [--max-instructions <n>] [--step-limit <n>] [--watchdog <n>] [--memory-limit <words>] [--dump-state-json <file> [--dump-state-memory]] [--dump-mem <file>[@<start>-<end>]] <image-file1|-> [image-file2]...");
+        return Err(2);
+    }
+
+    // OS要在所有用户镜像之前放进低地址，TRAP和中断才会走它的例程
+    if let Some(os) = with_os {
+        images.insert(0, (os.as_str(), None));
+        use_os = true;
+    }
+
+    // 初始化VM，内存和寄存器的布局见Vm::new
+    let mut vm = Vm::new();
+    vm.ascii_only = ascii_only;
+    vm.trap_exceptions = trap_exceptions;
+    vm.log_input = log_input;
+    vm.quiet = quiet;
+    vm.memory_limit = memory_limit;
+    vm.use_os = use_os;
+    vm.on_eof = on_eof;
+    vm.endian = endian;
+    vm.trap_overflow = trap_overflow;
+    vm.check_cond = check_cond;
+    vm.extensions = extensions;
+    if let Some(seed) = seed {
+        vm.rng = Rng::new(seed);
+    }
+    vm.warn_r7_clobber = warn_r7_clobber;
+    vm.warn_code_writes = warn_smc;
+    vm.warn_wild_jumps = warn_wild_jumps;
+    vm.watchpoints = watchpoints;
+    if profile {
+        vm.profile = Some(Profile::default());
+    }
+    if cycles {
+        vm.cycles = Some(Cycles::default());
+    }
+    if let Some(depth) = journal {
+        vm.journal = Some(Journal::new(depth));
+    }
+    // 键盘输入按顺序从脚本文件里取，读完之后按--on-eof处理，不再碰终端
+    if let Some(path) = input_script {
+        match fs::File::open(path) {
+            Ok(file) => vm.set_input(io::BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Failed to open input script {}: {}", path, e);
+                return Err(1);
+            }
+        }
+    }
+    // 程序输出的字符写到文件，诊断信息仍然在stderr。
+    // Vm自己攒着输出，HALT、阻塞读键盘和轮询KBSR时都会flush，程序卡住时已经输出的部分也在文件里
+    if let Some(path) = output_file {
+        match fs::File::create(path) {
+            Ok(file) => vm.sink = Some(Box::new(file)),
+            Err(e) => {
+                eprintln!("Failed to create output file {}: {}", path, e);
+                return Err(1);
+            }
+        }
+    }
+    match trace {
+        Some(None) => vm.trace = Some(Box::new(io::stderr())),
+        Some(Some(path)) => match fs::File::create(path) {
+            Ok(file) => vm.trace = Some(Box::new(io::BufWriter::new(file))),
+            Err(e) => {
+                eprintln!("Failed to create trace file {}: {}", path, e);
+                return Err(1);
+            }
+        },
+        None => {}
+    }
+    if let Some(addr) = origin {
+        vm.origin = addr;
+        vm.reg[Reg::PC] = addr;
+    }
+    if let Some(path) = symbols_path {
+        match fs::read_to_string(path)
+            .ok()
+            .and_then(|text| SymbolTable::from_text(&text))
+        {
+            Some(symbols) => vm.symbols = Some(symbols),
+            None => {
+                eprintln!("Failed to load symbols from {}", path);
+                return Err(1);
+            }
+        }
+    }
+    let mut breakpoints = Breakpoints::new();
+    for spec in break_specs {
+        let Some((addr, condition)) = split_spec(spec) else {
+            eprintln!(
+                "Error: --break 的条件写错了：{}，应该是 <addr> if R2 == 0xFFFF 这样",
+                spec
+            );
+            return Err(2);
+        };
+        match resolve_addr(addr, vm.symbols.as_ref()) {
+            Some(addr) => {
+                breakpoints.insert(addr, condition);
+            }
+            None => {
+                eprintln!(
+                    "Error: --break 的地址 {} 不是16位地址，也不在符号表里",
+                    addr
+                );
+                return Err(2);
+            }
+        }
     }
 
-    // 初始化VM内存
-    // LC-3有65536个内存位置，每个位置能存16bits值
-    // 所以一共内存有128KB
-    let mut memory = vec![0u16; 65536];
+    // 加载所有输入的镜像参数，"-"表示从stdin读。
+    // 这一步必须在切换终端原始模式之前完成，stdin先读完镜像才轮到键盘输入
+    // 已经加载的镜像和范围，后面的镜像和它们重叠时说明有一个被覆盖了
+    let mut loaded: Vec<(&str, Range<u32>)> = vec![];
+    for (image, load_at) in images {
+        let range = match vm.read_image(image, load_at) {
+            Ok(range) => range,
+            Err(e) => {
+                eprintln!("Failed to load image {}: {}", image, e);
+                return Err(1);
+            }
+        };
+
+        for (other, other_range) in &loaded {
+            if let Some(common) = overlap(&range, other_range) {
+                eprintln!(
+                    "{}: image {} overlaps {} at {:#06x}-{:#06x}",
+                    if no_overlap { "Error" } else { "Warning" },
+                    image,
+                    other,
+                    common.start,
+                    common.end - 1
+                );
+                if no_overlap {
+                    return Err(1);
+                }
+            }
+        }
 
-    // 加载所有输入的镜像参数
-    for i in 1..args.len() {
-        if !read_image(&args[i], &mut memory) {
-            println!("Failed to load image: {}", args[i]);
-            process::exit(1);
+        // --disasm只列出反汇编清单，不执行
+        if disasm {
+            print!(
+                "{}",
+                listing(&vm.memory, range.clone(), vm.symbols.as_ref())
+            );
         }
+        loaded.push((image, range));
+    }
+
+    if disasm {
+        return Ok(0);
     }
 
     // 标准控制台的默认行为是从用户获取输入，并仅在输入换行符（按 Enter 按钮）时才处理它们。 为了玩游戏，需要更改终端的默认行为。
-    // Platform Specifics (Unix here)
-    // Setting terminal input/output behaviour such as accepting
-    // character without the need for a newline character
-    // Refer: https://stackoverflow.com/questions/26321592/how-can-i-read-one-character-from-stdin-without-having-to-hit-enter
-    let stdin = 0;
-    let termios = Termios::from_fd(stdin).unwrap();
-    let mut new_termios = termios.clone(); // make a mutable copy of termios
-                                           // that we will modify
-    new_termios.c_iflag &= IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON;
-    new_termios.c_lflag &= !(ICANON | ECHO); // no echo and canonical mode
-    tcsetattr(stdin, TCSANOW, &mut new_termios).unwrap();
-    // Platform specific end
-
-    // 初始化Register
-    #[allow(non_snake_case)]
-    let PC_START: u16 = 0x3000; // PC默认的起始位置
-    let mut registers = vec![0u16; Reg::COUNT as usize];
-
-    registers[Reg::PC] = PC_START;
-
-    // 处理程序，步骤如下：
-    // 1.从内存中的寄存器地址加载一条指令PC。
-    // 2.增加PC寄存器。
-    // 3.查看操作码以确定它应该执行哪种类型的指令。
-    // 4.使用指令中的参数执行指令。
-    // 5.返回步骤1。
-    let mut running = true;
-
-    while running {
-        // 加载一条指令
-        let instr = mem_read(registers[Reg::PC], &mut memory);
-
-        // PC地址+1留待下次循环继续读取
-        registers[Reg::PC] += 1;
-
-        // 获取操作码
-        let opcode = instr >> 12;
-        //println!("Executing Instr {:#018b} and Opcode bit: {}", instr, opcode);
-
-        // 开始匹配action
-        match opcode {
-            code if code == OpCodes::OP_ADD as u16 => {
-                op_add(&mut registers, instr);
-            }
-            code if code == OpCodes::OP_AND as u16 => {
-                op_and(&mut registers, instr);
-            }
-            code if code == OpCodes::OP_BR as u16 => {
-                op_branch(&mut registers, instr);
-            }
-            code if code == OpCodes::OP_JMP as u16 => {
-                op_jump(&mut registers, instr);
-            }
-            code if code == OpCodes::OP_JSR as u16 => {
-                op_jsr(&mut registers, instr);
-            }
-            code if code == OpCodes::OP_LD as u16 => {
-                op_load(&mut registers, instr, &mut memory);
-            }
-            code if code == OpCodes::OP_LDI as u16 => {
-                op_ldi(&mut registers, instr, &mut memory);
-            }
-            code if code == OpCodes::OP_LDR as u16 => {
-                op_ldr(&mut registers, instr, &mut memory);
-            }
-            code if code == OpCodes::OP_LEA as u16 => {
-                op_lea(&mut registers, instr);
-            }
-            code if code == OpCodes::OP_NOT as u16 => {
-                op_not(&mut registers, instr);
-            }
-            code if code == OpCodes::OP_ST as u16 => {
-                op_st(&mut registers, instr, &mut memory);
-            }
-            code if code == OpCodes::OP_STI as u16 => {
-                op_sti(&mut registers, instr, &mut memory);
-            }
-            code if code == OpCodes::OP_STR as u16 => {
-                op_str(&mut registers, instr, &mut memory);
-            }
-            code if code == OpCodes::OP_RES as u16 => {
-                println!("Bad OpCode 'RES' received. Aborting.");
-                process::exit(10);
-            }
-            code if code == OpCodes::OP_RTI as u16 => {
-                println!("Bad OpCode 'RTI' received. Aborting.");
-                process::exit(10);
-            }
-            // 1111就是trap code
-            code if code == OpCodes::OP_TRAP as u16 => {
-                // 先处理最后8位以获取具体trapcode
-                let trapcode = instr & 0xFF;
-                // println!("Executing {} TRAP, Instr {:#018b}", trapcode, instr);
-
-                match trapcode {
-                    code if code == TrapCode::GETC as u16 => {
-                        trap_getc(&mut registers);
-                    }
-                    code if code == TrapCode::OUT as u16 => {
-                        trap_out(&mut registers);
-                    }
-                    code if code == TrapCode::PUTS as u16 => {
-                        trap_puts(&mut registers, &mut memory);
-                    }
-                    code if code == TrapCode::IN as u16 => {
-                        trap_in(&mut registers);
-                    }
-                    code if code == TrapCode::PUTSP as u16 => {
-                        trap_putsp(&mut registers, &mut memory);
-                    }
-                    code if code == TrapCode::HALT as u16 => {
-                        trap_halt();
-                        running = false;
-                    }
-                    _ => {
-                        println!("Invalid Trap Code received, aborting.");
-                        process::exit(21);
+    // guard在drop时恢复终端原来的设置；stdin不是终端时不需要切换
+    let raw_mode = RawMode::enable().ok();
+
+    // 处理程序，每次循环执行一条指令(见Vm::step)，直到HALT
+    let mut instructions: u64 = 0;
+    let mut stepping = debug;
+    // 调试器until命令的目标地址
+    let mut run_to: Option<u16> = None;
+
+    // 活锁检测：每隔window条指令给R0-R7和COND拍个快照，
+    // 连续几次快照都一样说明程序虽然在跑但没有任何进展(比如忘了给循环计数器加1)
+    const LIVELOCK_SNAPSHOTS: u32 = 3;
+    let mut livelock_snapshot: Vec<u16> = vec![];
+    let mut livelock_repeats = 0;
+
+    // 看门狗：一条指令执行完PC还是它自己(比如BR到自己)，连续threshold次就认为死循环了
+    let mut self_loops: u64 = 0;
+
+    let mut step_limit_reached = false;
+
+    while vm.running {
+        if let Some(window) = livelock_window {
+            if instructions > 0 && instructions.is_multiple_of(window) {
+                let mut snapshot = vm.reg.as_slice()[..8].to_vec();
+                snapshot.push(vm.reg[Reg::COND]);
+
+                if snapshot == livelock_snapshot {
+                    livelock_repeats += 1;
+                    // 每次卡住只警告一次
+                    if livelock_repeats == LIVELOCK_SNAPSHOTS {
+                        eprintln!(
+                            "Warning: possible livelock at {:#06x}, registers unchanged for {} instructions",
+                            vm.reg[Reg::PC],
+                            window * LIVELOCK_SNAPSHOTS as u64
+                        );
                     }
+                } else {
+                    livelock_snapshot = snapshot;
+                    livelock_repeats = 0;
+                }
+            }
+        }
+
+        let pc = vm.reg[Reg::PC];
+
+        // 跑满了上限就像HALT一样正常停机，方便CI里兜住死循环
+        if Some(instructions) == max_instructions {
+            vm.flush_output();
+            eprintln!(
+                "Instruction limit of {} reached at {:#06x}, halting.",
+                instructions, pc
+            );
+            break;
+        }
+
+        // 和--max-instructions一样是硬上限，但算作失败：停机之后以124退出，和timeout(1)一样
+        if Some(instructions) == step_limit {
+            vm.flush_output();
+            eprintln!(
+                "Step limit of {} reached at {:#06x}, aborting.",
+                instructions, pc
+            );
+            step_limit_reached = true;
+            break;
+        }
+
+        // 断点(包括条件)在执行之前检查，报告的地址就是这条指令自己的地址；
+        // 调试模式下单步时每条指令前都会回到REPL。
+        // until的目标不管是到了还是先在别处停下来，回到REPL时都作废
+        let hit = breakpoint_hit(&breakpoints, pc, &vm.reg);
+        if hit {
+            // 日志里记下这里命中了断点，rb可以退回来
+            if let Some(journal) = vm.journal.as_mut() {
+                journal.mark_breakpoint();
+            }
+        }
+        if stepping || hit || run_to == Some(pc) {
+            vm.flush_output();
+            run_to = None;
+            let command = if debug {
+                debug_repl(&mut vm, raw_mode.as_ref(), &mut breakpoints)
+            } else if breakpoint_prompt(&vm, pc) {
+                Command::Continue
+            } else {
+                Command::Quit
+            };
+
+            match command {
+                Command::Step => stepping = true,
+                Command::Continue => stepping = false,
+                Command::Until(addr) => {
+                    stepping = false;
+                    run_to = Some(addr);
+                }
+                Command::Quit => break,
+            }
+        }
+
+        instructions += 1;
+
+        let step = vm.step();
+
+        if let Some(threshold) = watchdog {
+            if vm.reg[Reg::PC] == pc {
+                self_loops += 1;
+                if self_loops == threshold {
+                    vm.flush_output();
+                    eprintln!("Possible infinite loop at {:#06x}, halting.", pc);
+                    break;
                 }
+            } else {
+                self_loops = 0;
             }
-            _ => {
-                println!("Invalid Opcode received, aborting current image.");
-                process::exit(20);
+        }
+
+        // 监视点在写入完成之后报告，调试模式下回到REPL，否则像断点一样暂停
+        if let Some(hit) = vm.watch_hit.take() {
+            vm.flush_output();
+            eprintln!(
+                "Watchpoint {:#06x}: {} -> {} written by instruction at {:#06x}",
+                hit.addr,
+                format_word(hit.old),
+                format_word(hit.new),
+                hit.pc
+            );
+            if debug {
+                stepping = true;
+            } else if !pause_prompt() {
+                break;
             }
         }
+
+        if let Err(error) = step {
+            vm.flush_output();
+            let code = report_error(&vm, &error);
+            // 调试模式下不退出，回到REPL方便检查现场；输出都写不出去了就没法调试了
+            if !debug || matches!(error, VmError::Io(_)) {
+                return Err(code);
+            }
+            stepping = true;
+        }
+    }
+
+    vm.flush_output();
+    // 写到stderr，程序自己的输出被管道接走时不会混进去
+    if !quiet {
+        eprintln!("Executed {} instructions", instructions);
+    }
+    if let Some(cycles) = &vm.cycles {
+        eprintln!("Estimated {} cycles", cycles.total);
+    }
+    if let Some(profile) = &vm.profile {
+        eprint!("{}", profile.report());
     }
 
     // reset the stdin to original termios data
-    tcsetattr(stdin, TCSANOW, &termios).unwrap();
+    drop(raw_mode);
+
+    // 停机后把机器状态输出成JSON，给外部工具用
+    if let Some(path) = dump_state {
+        let state = MachineState::capture(&vm.reg, &vm.memory, instructions, dump_state_memory);
+        if let Err(e) = fs::write(path, state.to_json()) {
+            eprintln!("Failed to write machine state to {}: {}", path, e);
+            return Err(1);
+        }
+    }
+
+    // 停机后把内存写回成可以重新加载的镜像；默认是设备寄存器区以下的全部内存
+    if let Some((path, range)) = dump_mem {
+        let end = vm.memory.len().min(DEVICE_PAGE_START as usize) - 1;
+        let range = range.unwrap_or(0..=end as u16);
+        let result = fs::File::create(path).and_then(|file| vm.dump_memory(range, file));
+        if let Err(e) = result {
+            eprintln!("Failed to dump memory to {}: {}", path, e);
+            return Err(1);
+        }
+    }
+
+    if !quiet {
+        eprintln!("Shutting Down VM...");
+    }
+
+    if step_limit_reached {
+        return Err(STEP_LIMIT_EXIT_CODE);
+    }
+
+    // 很多测试脚本把HALT时R0的值当作程序的退出码；
+    // 只有真正执行了HALT才算，被上限、看门狗或者调试器中止的不算
+    if exit_code_from_r0 && !vm.running {
+        return Ok(vm.reg[Reg::R0] as u8);
+    }
+    Ok(0)
+}
+
+/// 报告step返回的错误，返回对应的退出码。
+/// 遇到无法执行的指令时再把它前后几条指令反汇编出来，
+/// 方便判断是跳错了地方还是数据被当成了代码
+fn report_error(vm: &Vm, error: &VmError) -> u8 {
+    let (pc, code) = match *error {
+        VmError::BadOpcode { pc, .. } => {
+            eprintln!("Bad OpCode 'RES' received at {:#06x}. Aborting.", pc);
+            (pc, 10)
+        }
+        VmError::IllegalInstruction { pc, .. } => {
+            eprintln!(
+                "Privilege mode violation: RTI executed in user mode at {:#06x}. Aborting.",
+                pc
+            );
+            (pc, 10)
+        }
+        VmError::BadTrap { pc, vector } => {
+            eprintln!(
+                "Invalid Trap Code {:#04x} received at {:#06x}, aborting.",
+                vector, pc
+            );
+            (pc, 21)
+        }
+        VmError::BadCond { pc, .. } => {
+            eprintln!("--check-cond: {}. Aborting.", error);
+            (pc, 1)
+        }
+        VmError::OutOfBounds { pc, fault } => {
+            eprintln!(
+                "Illegal memory {} at {:#06x} by instruction at {:#06x}. Aborting.",
+                if fault.write { "write" } else { "read" },
+                fault.addr,
+                pc
+            );
+            return 11;
+        }
+        _ => {
+            eprintln!("Error: {}", error);
+            return 1;
+        }
+    };
+
+    let start = pc.saturating_sub(BAD_INSTRUCTION_CONTEXT);
+    let end = pc.saturating_add(BAD_INSTRUCTION_CONTEXT + 1);
+    for addr in (start..end).filter(|&addr| (addr as usize) < vm.memory.len()) {
+        let word = vm.peek(addr);
+        eprintln!(
+            "{} {:#06x}: {:#06x}  {}",
+            if addr == pc { "=>" } else { "  " },
+            addr,
+            word,
+            disassemble_with(word, addr, vm.symbols.as_ref())
+        );
+    }
+    code
+}
+
+/// --step-limit用完时的退出码
+const STEP_LIMIT_EXIT_CODE: u8 = 124;
+
+/// 报告无法执行的指令时，前后各列出多少个字
+const BAD_INSTRUCTION_CONTEXT: u16 = 3;
+
+/// 调试器REPL返回给主循环的指令
+enum Command {
+    /// 执行一条指令后回到REPL
+    Step,
+    /// 一直运行到下一个断点或者HALT
+    Continue,
+    /// 运行到PC等于这个地址，只生效一次，途中遇到断点或者HALT也会停
+    Until(u16),
+    Quit,
+}
+
+/// --debug模式下的交互命令行：
+///   s            单步执行一条指令
+///   c            继续运行，直到断点或者HALT
+///   until <addr> 运行到addr(或者label)，相当于一个用一次就删掉的断点
+///   r            打印所有寄存器
+///   m <addr> [n] 从addr开始打印n个内存字(默认16个)
+///   b <addr> [if R2 == 0xFFFF]
+///                在addr(或者label)设断点，可以带一个寄存器条件
+///   back         撤销上一条指令，需要--journal
+///   rb           倒退到日志里最近一次命中断点的地方，需要--journal
+///   find <w>...  找出存着这串字的地址
+///   find-str <s> 找出存着字符串s的地址，PUTS和PUTSP两种格式都找，s可以加双引号
+///   save <file>  把完整的虚拟机状态存成快照
+///   load <file>  从快照恢复，之后从快照里的PC继续
+///   q            退出
+/// 读命令时临时切回终端原来的模式，这样输入有回显、可以按行编辑
+fn debug_repl(vm: &mut Vm, raw_mode: Option<&RawMode>, breakpoints: &mut Breakpoints) -> Command {
+    let pc = vm.reg[Reg::PC];
+    let instr = vm.peek(pc);
+    eprintln!(
+        "{:#06x}: {:#06x}  {}",
+        pc,
+        instr,
+        disassemble_with(instr, pc, vm.symbols.as_ref())
+    );
+
+    if let Some(raw_mode) = raw_mode {
+        raw_mode.suspend().unwrap();
+    }
+    let command = loop {
+        eprint!("(lc3) ");
+        io::stderr().flush().unwrap();
+
+        // 和程序的键盘输入一样不经过std的Stdin缓冲，stdin是管道时两边的字节不会互相吞掉。
+        // 命令读完了(或者stdin读不了)就没法再调试，当作q
+        let Ok(Some(line)) = keyboard::read_line() else {
+            break Command::Quit;
+        };
 
-    println!("Shutting Down VM...");
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            ["s"] => break Command::Step,
+            ["c"] => break Command::Continue,
+            ["q"] => break Command::Quit,
+            ["until", addr] => match resolve_addr(addr, vm.symbols.as_ref()) {
+                Some(addr) => break Command::Until(addr),
+                None => eprintln!("Unknown address or label {}", addr),
+            },
+            ["r"] => eprint!("{}", dump_registers(&vm.reg)),
+            ["save", path] => match vm.save_snapshot(path) {
+                Ok(()) => eprintln!("Saved snapshot to {}", path),
+                Err(e) => eprintln!("Failed to save snapshot: {}", e),
+            },
+            ["load", path] => match vm.load_snapshot(path) {
+                Ok(()) => eprintln!("Loaded snapshot, PC = {:#06x}", vm.reg[Reg::PC]),
+                Err(e) => eprintln!("Failed to load snapshot: {}", e),
+            },
+            ["m", addr, rest @ ..] => {
+                let count = match rest {
+                    [] => Some(16),
+                    [n] => n.parse::<usize>().ok(),
+                    _ => None,
+                };
+                match (resolve_addr(addr, vm.symbols.as_ref()), count) {
+                    (Some(addr), Some(count)) => dump_memory(vm, addr, count),
+                    _ => eprintln!("Usage: m <addr> [count]"),
+                }
+            }
+            ["back"] => {
+                if vm.journal.is_none() {
+                    eprintln!("Journaling is off, start the VM with --journal <n>");
+                } else if vm.step_back() {
+                    let pc = vm.reg[Reg::PC];
+                    let instr = vm.peek(pc);
+                    eprintln!(
+                        "{:#06x}: {:#06x}  {}",
+                        pc,
+                        instr,
+                        disassemble_with(instr, pc, vm.symbols.as_ref())
+                    );
+                } else {
+                    eprintln!("No more steps to undo");
+                }
+            }
+            ["rb"] => {
+                if vm.journal.is_none() {
+                    eprintln!("Journaling is off, start the VM with --journal <n>");
+                } else if let Some(steps) = vm.rewind_to_breakpoint() {
+                    let pc = vm.reg[Reg::PC];
+                    let instr = vm.memory[pc as usize];
+                    eprintln!(
+                        "Rewound {} steps to breakpoint at {:#06x}: {:#06x}  {}",
+                        steps,
+                        pc,
+                        instr,
+                        disassemble_with(instr, pc, vm.symbols.as_ref())
+                    );
+                } else {
+                    let depth = vm.journal.as_ref().map_or(0, |journal| journal.len());
+                    eprintln!("No breakpoint hit in the last {} steps", depth);
+                }
+            }
+            ["find", values @ ..] if !values.is_empty() => {
+                match values.iter().map(|v| parse_u16(v)).collect::<Option<Vec<_>>>() {
+                    Some(pattern) => {
+                        let found = find_words(&vm.memory, &pattern);
+                        print_matches(found.iter().map(|addr| format!("{:#06x}", addr)));
+                    }
+                    None => eprintln!("Usage: find <word> [word]..."),
+                }
+            }
+            ["find-str", ..] => {
+                let text = line.trim()["find-str".len()..].trim();
+                let text = text
+                    .strip_prefix('"')
+                    .and_then(|t| t.strip_suffix('"'))
+                    .unwrap_or(text);
+                let found = find_string(&vm.memory, text.as_bytes());
+                print_matches(found.iter().map(|(addr, encoding)| {
+                    format!("{:#06x} ({:?})", addr, encoding)
+                }));
+            }
+            ["b", ..] => match split_spec(&line.trim()[1..]) {
+                Some((addr, condition)) => match resolve_addr(addr, vm.symbols.as_ref()) {
+                    Some(addr) => {
+                        breakpoints.insert(addr, condition);
+                        eprintln!("Breakpoint set at {:#06x}", addr);
+                    }
+                    None => eprintln!("Unknown address or label {}", addr),
+                },
+                None => eprintln!("Usage: b <addr> [if <reg> <op> <value>]"),
+            },
+            [] => {}
+            _ => eprintln!("Commands: s(tep), c(ontinue), until <addr>, r(egisters), m <addr> [count], b <addr> [if <reg> <op> <value>], back, rb, find <word>..., find-str <text>, save <file>, load <file>, q(uit)"),
+        }
+    };
+    if let Some(raw_mode) = raw_mode {
+        raw_mode.resume().unwrap();
+    }
+
+    command
+}
+
+/// find最多列出多少个地址，剩下的只报个数
+const FIND_LIMIT: usize = 32;
+
+/// 列出find找到的地址
+fn print_matches(matches: impl ExactSizeIterator<Item = String>) {
+    let total = matches.len();
+    if total == 0 {
+        eprintln!("Not found");
+        return;
+    }
+    for line in matches.take(FIND_LIMIT) {
+        eprintln!("  {}", line);
+    }
+    if total > FIND_LIMIT {
+        eprintln!("  ... {} more", total - FIND_LIMIT);
+    }
+}
+
+/// 每行8个字打印一段内存，用peek读，不会触发KBSR的副作用
+fn dump_memory(vm: &Vm, addr: u16, count: usize) {
+    for row in (0..count).step_by(8) {
+        let start = addr.wrapping_add(row as u16);
+        eprint!("{:#06x}:", start);
+        for i in row..count.min(row + 8) {
+            eprint!(" {:04x}", vm.peek(addr.wrapping_add(i as u16)));
+        }
+        eprintln!();
+    }
+}
+
+/// 命中断点时暂停，打印地址和指令，按任意键继续，按q退出
+/// 返回false表示用户选择退出
+fn breakpoint_prompt(vm: &Vm, pc: u16) -> bool {
+    let instr = vm.peek(pc);
+    eprintln!(
+        "Breakpoint at {:#06x}: {:#06x}  {}",
+        pc,
+        instr,
+        disassemble_with(instr, pc, vm.symbols.as_ref())
+    );
+    pause_prompt()
+}
+
+/// 等用户按一个键，返回false表示按了q要退出。
+/// stdin已经结束(比如重定向自/dev/null)时没人能按键了，也当作退出
+fn pause_prompt() -> bool {
+    eprint!("Press any key to continue, 'q' to quit...");
+    io::stderr().flush().unwrap();
+
+    let key = keyboard::read_byte();
+    eprintln!();
+
+    matches!(key, Ok(Some(key)) if key != b'q')
+}
+
+/// 断点地址和各自的条件，没有条件的断点每次都停
+type Breakpoints = HashMap<u16, Option<Condition>>;
+
+/// pc上有断点，而且断点没有条件或者条件成立
+fn breakpoint_hit(breakpoints: &Breakpoints, pc: u16, reg: &Registers) -> bool {
+    match breakpoints.get(&pc) {
+        Some(Some(condition)) => condition.holds(reg),
+        Some(None) => true,
+        None => false,
+    }
+}
+
+/// 调试器和--break里的地址：先按数字解析，不是数字时在符号表里找label
+fn resolve_addr(text: &str, symbols: Option<&SymbolTable>) -> Option<u16> {
+    parse_u16(text).or_else(|| symbols.and_then(|symbols| symbols.address(text)))
+}
+
+/// 解析--load的参数 file@addr：没有文件头的原始代码，从addr开始放
+fn parse_load(spec: &str) -> Option<(&str, Option<u16>)> {
+    let (path, addr) = spec.rsplit_once('@')?;
+    Some((path, Some(parse_u16(addr)?)))
+}
+
+/// 解析--dump-mem的参数 file@start-end，地址范围包含两端；
+/// @后面不是地址范围时整个参数都当作文件名，表示写出整个内存
+fn parse_dump_mem(spec: &str) -> (&str, Option<RangeInclusive<u16>>) {
+    let range = spec.rsplit_once('@').and_then(|(path, range)| {
+        let (start, end) = range.split_once('-')?;
+        Some((path, parse_u16(start)?..=parse_u16(end)?))
+    });
+    match range {
+        Some((path, range)) => (path, Some(range)),
+        None => (spec, None),
+    }
+}
+
+/// 解析命令行里的16位数值，支持0x/x开头的十六进制和十进制
+fn parse_u16(text: &str) -> Option<u16> {
+    match text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('x'))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
 }
@@ -1,15 +1,15 @@
 /// 以下实现规则可以从assets/lc3-isa.pdf中找到
-use lc_3_vm::register::Reg;
-use lc_3_vm::{mem_read, mem_write, sign_extend, update_flags};
+use crate::register::{Reg, Registers};
+use crate::{sign_extend, to_signed, update_flags, with_vm, Cond, Vm, PSR_USER};
 
-/// 注意：将传递到我们的模拟器的汇编代码
-/// 严重依赖整数溢出加法来进行环绕。
-/// Rust 不允许在正常添加中这样做，例如：let a: u16 = 65535 + 1
-/// 会产生错误。 为此，我们使用了 u16::wrapping_add() 函数。
-/// u16::wrapping_add(65536, 1) 与 65535 + 1 相同，在这种情况下产生 0。
+// 注意：将传递到我们的模拟器的汇编代码
+// 严重依赖整数溢出加法来进行环绕。
+// Rust 不允许在正常添加中这样做，例如：let a: u16 = 65535 + 1
+// 会产生错误。 为此，我们使用了 u16::wrapping_add() 函数。
+// u16::wrapping_add(65536, 1) 与 65535 + 1 相同，在这种情况下产生 0。
 
 /// Add
-pub fn op_add(reg: &mut Vec<u16>, instr: u16) {
+pub fn op_add(reg: &mut Registers, instr: u16) {
     // 以下的into()是用于从u16转换成usize的
     let r0: usize = ((instr >> 9) & 0x07).into(); // DR(destination register)
     let r1: usize = ((instr >> 6) & 0x07).into(); // SR1(getting first operand register)
@@ -27,8 +27,25 @@ pub fn op_add(reg: &mut Vec<u16>, instr: u16) {
     update_flags(r0, reg);
 }
 
+/// ADD按有符号16位解释时是否溢出：两个操作数同号而结果异号。
+/// 溢出时返回两个操作数，不改动寄存器
+pub fn add_overflows(reg: &Registers, instr: u16) -> Option<(u16, u16)> {
+    let r1: usize = ((instr >> 6) & 0x07).into();
+    let a = reg[r1];
+    let b = if (instr >> 5) & 0x01 == 1 {
+        sign_extend(instr & 0x1f, 5)
+    } else {
+        reg[(instr & 0x07) as usize]
+    };
+
+    to_signed(a)
+        .checked_add(to_signed(b))
+        .is_none()
+        .then_some((a, b))
+}
+
 /// //Bitwise And
-pub fn op_and(reg: &mut Vec<u16>, instr: u16) {
+pub fn op_and(reg: &mut Registers, instr: u16) {
     let r0: usize = ((instr >> 9) & 0x07).into();
     let r1: usize = ((instr >> 6) & 0x07).into();
     let imm_flag: u16 = (instr >> 5) & 0x01;
@@ -45,7 +62,7 @@ pub fn op_and(reg: &mut Vec<u16>, instr: u16) {
 }
 
 /// Bitwise Not
-pub fn op_not(reg: &mut Vec<u16>, instr: u16) {
+pub fn op_not(reg: &mut Registers, instr: u16) {
     let r0: usize = ((instr >> 9) & 0x07).into();
     let r1: usize = ((instr >> 6) & 0x07).into();
 
@@ -54,24 +71,25 @@ pub fn op_not(reg: &mut Vec<u16>, instr: u16) {
 }
 
 /// Branch
-pub fn op_branch(reg: &mut Vec<u16>, instr: u16) {
+pub fn op_branch(reg: &mut Registers, instr: u16) {
     let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
-    let cond_flag: u16 = (instr >> 9) & 0x07;
+    let nzp = Cond::from_bits(instr >> 9);
 
-    if (cond_flag & reg[Reg::COND]) > 0 {
+    if nzp.intersects(Cond::from_bits(reg[Reg::COND])) {
         reg[Reg::PC] = u16::wrapping_add(reg[Reg::PC], pc_offset);
     }
 }
 
 /// Note: RET is actually just a special case of JUMP
-pub fn op_jump(reg: &mut Vec<u16>, instr: u16) {
+/// 基址寄存器是R7时就是RET，执行上没有区别，反汇编和trace里显示成RET
+pub fn op_jump(reg: &mut Registers, instr: u16) {
     let r1: usize = ((instr >> 6) & 0x07).into();
 
     reg[Reg::PC] = reg[r1];
 }
 
 /// Jump Register
-pub fn op_jsr(reg: &mut Vec<u16>, instr: u16) {
+pub fn op_jsr(reg: &mut Registers, instr: u16) {
     let long_flag: u16 = (instr >> 11) & 1;
     reg[Reg::R7] = reg[Reg::PC];
 
@@ -84,48 +102,25 @@ pub fn op_jsr(reg: &mut Vec<u16>, instr: u16) {
     }
 }
 
-/// "Load - An address is computed by sign-extending bits [8:0]
-/// to 16 bits and adding this value to the incremented PC. The
-/// contents of memory at this address are loaded into DR. The
-/// condition codes are set, based on whether the value loaded
-/// is negative, zero, or positive."
-pub fn op_load(reg: &mut Vec<u16>, instr: u16, memory: &mut Vec<u16>) {
-    let r0: usize = ((instr >> 9) & 0x7).into();
-    let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
-
-    reg[r0] = mem_read(u16::wrapping_add(reg[Reg::PC], pc_offset), memory);
-    update_flags(r0, reg);
+/// 同Vm::op_load，不经过map_device映射的设备
+pub fn op_load(reg: &mut Registers, instr: u16, memory: &mut Vec<u16>) {
+    with_vm(reg, memory, |vm| vm.op_load(instr));
 }
 
-/// Load Indirect - Load a value from a location in memory into register
-pub fn op_ldi(reg: &mut Vec<u16>, instr: u16, memory: &mut Vec<u16>) {
-    let r0: usize = ((instr >> 9) & 0x07).into();
-    let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
-
-    reg[r0] = mem_read(
-        mem_read(u16::wrapping_add(reg[Reg::PC], pc_offset), memory),
-        memory,
-    );
-    update_flags(r0, reg);
+/// 同Vm::op_ldi，不经过map_device映射的设备
+pub fn op_ldi(reg: &mut Registers, instr: u16, memory: &mut Vec<u16>) {
+    with_vm(reg, memory, |vm| vm.op_ldi(instr));
 }
 
-/// "Load Register - An address is computed by sign-extending bits
-/// [5:0] to 16 bits and adding this value to the contents of the
-/// register specified by bits [8:6]. The contents of memory at
-/// this address are loaded into DR.
-pub fn op_ldr(reg: &mut Vec<u16>, instr: u16, memory: &mut Vec<u16>) {
-    let r0: usize = ((instr >> 9) & 0x7).into();
-    let r1: usize = ((instr >> 6) & 0x7).into();
-    let offset: u16 = sign_extend(instr & 0x3F, 6);
-
-    reg[r0] = mem_read(u16::wrapping_add(reg[r1], offset), memory);
-    update_flags(r0, reg);
+/// 同Vm::op_ldr，不经过map_device映射的设备
+pub fn op_ldr(reg: &mut Registers, instr: u16, memory: &mut Vec<u16>) {
+    with_vm(reg, memory, |vm| vm.op_ldr(instr));
 }
 
 /// "Load Effective Address - An address is computed by sign-extending
 /// bits [8:0] to 16 bits and adding this value to the incremented PC.
 /// This address is loaded into DR."
-pub fn op_lea(reg: &mut Vec<u16>, instr: u16) {
+pub fn op_lea(reg: &mut Registers, instr: u16) {
     let r0: usize = ((instr >> 9) & 0x07).into();
     let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
 
@@ -133,40 +128,155 @@ pub fn op_lea(reg: &mut Vec<u16>, instr: u16) {
     update_flags(r0, reg);
 }
 
-/// "Store - The contents of the register specified by SR are stored
-/// in the memory location whose address is computed by sign-extending
-/// bits [8:0] to 16 bits and adding this value to the incremented PC."
-pub fn op_st(reg: &mut Vec<u16>, instr: u16, memory: &mut Vec<u16>) {
-    let r0: usize = ((instr >> 9) & 0x07).into();
-    let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
-
-    mem_write(u16::wrapping_add(reg[Reg::PC], pc_offset), reg[r0], memory);
+/// 同Vm::op_st，不经过map_device映射的设备
+pub fn op_st(reg: &mut Registers, instr: u16, memory: &mut Vec<u16>) {
+    with_vm(reg, memory, |vm| vm.op_st(instr));
 }
 
-/// "Store Indirect Address - The contents of the register specified
-/// by SR are stored in the memory location whose address is obtained as
-/// follows: Bits [8:0] are sign-extended to 16 bits and added to the
-/// incremented PC. What is in memory at this address is the address of
-/// the location to which the data in SR is stored."
-pub fn op_sti(reg: &mut Vec<u16>, instr: u16, memory: &mut Vec<u16>) {
-    let r0: usize = ((instr >> 9) & 0x07).into();
-    let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
+/// 同Vm::op_sti，不经过map_device映射的设备
+pub fn op_sti(reg: &mut Registers, instr: u16, memory: &mut Vec<u16>) {
+    with_vm(reg, memory, |vm| vm.op_sti(instr));
+}
 
-    mem_write(
-        mem_read(u16::wrapping_add(reg[Reg::PC], pc_offset), memory),
-        reg[r0],
-        memory,
-    );
+/// 同Vm::op_str，不经过map_device映射的设备
+pub fn op_str(reg: &mut Registers, instr: u16, memory: &mut Vec<u16>) {
+    with_vm(reg, memory, |vm| vm.op_str(instr));
 }
 
-/// "Store Register - The contents of the register specified by SR
-/// are stored in the memory location whose address is computed by
-/// sign-extending bits [5:0] to 16 bits and adding this value to
-/// the contents of the register specified by bits [8:6]."
-pub fn op_str(reg: &mut Vec<u16>, instr: u16, memory: &mut Vec<u16>) {
-    let r0: usize = ((instr >> 9) & 0x07).into();
-    let r1: usize = ((instr >> 6) & 0x07).into();
-    let offset: u16 = sign_extend(instr & 0x3F, 6);
+/// Vm上的指令方法：只碰寄存器的转调上面的自由函数，访问内存的实现在下面
+impl Vm {
+    /// --trap-overflow时在stderr报告有符号溢出，结果仍然按ISA回绕
+    pub fn op_add(&mut self, instr: u16) {
+        if self.trap_overflow {
+            if let Some((a, b)) = add_overflows(&self.reg, instr) {
+                eprintln!(
+                    "Signed overflow in ADD at {:#06x}: {} + {} wraps to {}",
+                    self.reg[Reg::PC].wrapping_sub(1),
+                    to_signed(a),
+                    to_signed(b),
+                    to_signed(a.wrapping_add(b))
+                );
+            }
+        }
+        op_add(&mut self.reg, instr);
+    }
+
+    pub fn op_and(&mut self, instr: u16) {
+        op_and(&mut self.reg, instr);
+    }
+
+    pub fn op_not(&mut self, instr: u16) {
+        op_not(&mut self.reg, instr);
+    }
+
+    pub fn op_branch(&mut self, instr: u16) {
+        op_branch(&mut self.reg, instr);
+    }
 
-    mem_write(u16::wrapping_add(reg[r1], offset), reg[r0], memory);
+    pub fn op_jump(&mut self, instr: u16) {
+        op_jump(&mut self.reg, instr);
+    }
+
+    pub fn op_jsr(&mut self, instr: u16) {
+        op_jsr(&mut self.reg, instr);
+    }
+
+    pub fn op_lea(&mut self, instr: u16) {
+        op_lea(&mut self.reg, instr);
+    }
+
+    /// "Load - An address is computed by sign-extending bits [8:0]
+    /// to 16 bits and adding this value to the incremented PC. The
+    /// contents of memory at this address are loaded into DR. The
+    /// condition codes are set, based on whether the value loaded
+    /// is negative, zero, or positive."
+    pub fn op_load(&mut self, instr: u16) {
+        let r0: usize = ((instr >> 9) & 0x7).into();
+        let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
+
+        self.reg[r0] = self.mem_read(u16::wrapping_add(self.reg[Reg::PC], pc_offset));
+        update_flags(r0, &mut self.reg);
+    }
+
+    /// Load Indirect - Load a value from a location in memory into register
+    pub fn op_ldi(&mut self, instr: u16) {
+        let r0: usize = ((instr >> 9) & 0x07).into();
+        let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
+
+        let addr = self.mem_read(u16::wrapping_add(self.reg[Reg::PC], pc_offset));
+        self.reg[r0] = self.mem_read(addr);
+        update_flags(r0, &mut self.reg);
+    }
+
+    /// "Load Register - An address is computed by sign-extending bits
+    /// [5:0] to 16 bits and adding this value to the contents of the
+    /// register specified by bits [8:6]. The contents of memory at
+    /// this address are loaded into DR.
+    pub fn op_ldr(&mut self, instr: u16) {
+        let r0: usize = ((instr >> 9) & 0x7).into();
+        let r1: usize = ((instr >> 6) & 0x7).into();
+        let offset: u16 = sign_extend(instr & 0x3F, 6);
+
+        self.reg[r0] = self.mem_read(u16::wrapping_add(self.reg[r1], offset));
+        update_flags(r0, &mut self.reg);
+    }
+
+    /// "Store - The contents of the register specified by SR are stored
+    /// in the memory location whose address is computed by sign-extending
+    /// bits [8:0] to 16 bits and adding this value to the incremented PC."
+    pub fn op_st(&mut self, instr: u16) {
+        let r0: usize = ((instr >> 9) & 0x07).into();
+        let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
+
+        self.mem_write(
+            u16::wrapping_add(self.reg[Reg::PC], pc_offset),
+            self.reg[r0],
+        );
+    }
+
+    /// "Store Indirect Address - The contents of the register specified
+    /// by SR are stored in the memory location whose address is obtained as
+    /// follows: Bits [8:0] are sign-extended to 16 bits and added to the
+    /// incremented PC. What is in memory at this address is the address of
+    /// the location to which the data in SR is stored."
+    pub fn op_sti(&mut self, instr: u16) {
+        let r0: usize = ((instr >> 9) & 0x07).into();
+        let pc_offset: u16 = sign_extend(instr & 0x1FF, 9);
+
+        let addr = self.mem_read(u16::wrapping_add(self.reg[Reg::PC], pc_offset));
+        self.mem_write(addr, self.reg[r0]);
+    }
+
+    /// "Store Register - The contents of the register specified by SR
+    /// are stored in the memory location whose address is computed by
+    /// sign-extending bits [5:0] to 16 bits and adding this value to
+    /// the contents of the register specified by bits [8:6]."
+    pub fn op_str(&mut self, instr: u16) {
+        let r0: usize = ((instr >> 9) & 0x07).into();
+        let r1: usize = ((instr >> 6) & 0x07).into();
+        let offset: u16 = sign_extend(instr & 0x3F, 6);
+
+        self.mem_write(u16::wrapping_add(self.reg[r1], offset), self.reg[r0]);
+    }
+
+    /// "Return from Interrupt - If the processor is running in Supervisor
+    /// mode, the top two elements on the Supervisor Stack are popped and
+    /// loaded into PC, PSR. If the processor is running in User mode, a
+    /// privilege mode violation exception occurs."
+    /// 用户模式下的检查由调用方负责，这里只处理特权模式的情况
+    pub fn op_rti(&mut self, _instr: u16) {
+        let sp = self.reg[Reg::R6];
+        self.reg[Reg::PC] = self.mem_read(sp);
+        let psr = self.mem_read(sp.wrapping_add(1));
+        self.reg[Reg::R6] = sp.wrapping_add(2);
+
+        self.reg[Reg::PSR] = psr;
+        self.set_cond_codes(Cond::from_bits(psr));
+
+        // 恢复出来的PSR是用户模式的话，要把R6换回用户栈
+        if psr & PSR_USER != 0 {
+            self.saved_ssp = self.reg[Reg::R6];
+            self.reg[Reg::R6] = self.saved_usp;
+        }
+    }
 }
@@ -0,0 +1,73 @@
+//! --profile用的执行频率统计：每种opcode、每个trap vector各执行了多少次。
+//! 计数在Vm::step_once里取出opcode和trap vector之后集中完成，不用改每个分支。
+
+use crate::disasm::{extension_trap_name, trap_name};
+
+/// 按opcode的值排列的助记符
+const OPCODE_NAMES: [&str; 16] = [
+    "BR", "ADD", "LD", "ST", "JSR", "AND", "LDR", "STR", "RTI", "NOT", "LDI", "STI", "JMP", "RES",
+    "LEA", "TRAP",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// 下标是opcode(instr >> 12)
+    pub opcodes: [u64; 16],
+    /// 下标是trap vector(instr & 0xFF)
+    pub traps: [u64; 256],
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            opcodes: [0; 16],
+            traps: [0; 256],
+        }
+    }
+}
+
+impl Profile {
+    /// 格式化成按次数从多到少排列的直方图，没执行过的opcode和trap不列出来
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+
+        let total: u64 = self.opcodes.iter().sum();
+        out.push_str(&format!("Opcode histogram ({} instructions):\n", total));
+        let opcodes = self
+            .opcodes
+            .iter()
+            .enumerate()
+            .map(|(op, &count)| (OPCODE_NAMES[op].to_string(), count));
+        histogram(&mut out, opcodes, total);
+
+        let total: u64 = self.traps.iter().sum();
+        if total > 0 {
+            out.push_str(&format!("Trap histogram ({} traps):\n", total));
+            let traps = self.traps.iter().enumerate().map(|(vector, &count)| {
+                let name = match trap_name(vector as u16).or(extension_trap_name(vector as u16)) {
+                    Some(name) => name.to_string(),
+                    None => format!("TRAP {:#04x}", vector),
+                };
+                (name, count)
+            });
+            histogram(&mut out, traps, total);
+        }
+
+        out
+    }
+}
+
+/// 每行一个名字、次数和占total的百分比，次数相同时保持原来的顺序
+fn histogram(out: &mut String, rows: impl Iterator<Item = (String, u64)>, total: u64) {
+    let mut rows = rows.filter(|(_, count)| *count > 0).collect::<Vec<_>>();
+    rows.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+    for (name, count) in rows {
+        out.push_str(&format!(
+            "  {:<10} {:>10} {:>6.2}%\n",
+            name,
+            count,
+            count as f64 * 100.0 / total as f64
+        ));
+    }
+}
@@ -0,0 +1,43 @@
+//! 扩展trap RAND用的伪随机数发生器：xorshift32，移位参数(13, 17, 5)。
+//! 每次调用先推进状态，再取状态的高16位(低位的随机性比较差)。
+//! 同一个种子在任何机器上得到的序列都一样，--seed就是为了让程序的运行可以重现。
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 种子是0时xorshift会一直输出0，换成这个固定的非0值
+const ZERO_SEED_REPLACEMENT: u32 = 0x2545_F491;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Rng {
+        Rng {
+            state: if seed == 0 {
+                ZERO_SEED_REPLACEMENT
+            } else {
+                seed
+            },
+        }
+    }
+
+    /// 没有--seed时的默认种子：当前时间，每次运行都不一样
+    pub fn from_time() -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u32)
+            .unwrap_or(0);
+        Rng::new(nanos)
+    }
+
+    pub fn next_u16(&mut self) -> u16 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x >> 16) as u16
+    }
+}
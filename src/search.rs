@@ -0,0 +1,55 @@
+//! 调试器find/find-str用的内存搜索，只读memory，不会触发设备寄存器的副作用。
+
+/// 字符串在内存里的存放方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringEncoding {
+    /// PUTS的格式：每个字的低8位一个字符，高8位是0
+    Puts,
+    /// PUTSP的格式：每个字先低8位、再高8位各一个字符
+    Putsp,
+}
+
+/// 所有以pattern这串字开头的地址，pattern为空时没有结果
+pub fn find_words(memory: &[u16], pattern: &[u16]) -> Vec<u16> {
+    if pattern.is_empty() {
+        return vec![];
+    }
+    memory
+        .windows(pattern.len())
+        .enumerate()
+        .filter(|(_, window)| *window == pattern)
+        .map(|(addr, _)| addr as u16)
+        .collect()
+}
+
+/// 所有存着text的地址，PUTS和PUTSP两种格式都找，按地址排列。
+/// PUTSP的字符串总是从一个字的低8位开始；奇数长度时最后一个字只比较低8位，
+/// 这样更长的字符串中间的前缀也能找到
+pub fn find_string(memory: &[u16], text: &[u8]) -> Vec<(u16, StringEncoding)> {
+    if text.is_empty() {
+        return vec![];
+    }
+
+    let puts = text.iter().map(|&b| b as u16).collect::<Vec<_>>();
+    let mut found = find_words(memory, &puts)
+        .into_iter()
+        .map(|addr| (addr, StringEncoding::Puts))
+        .collect::<Vec<_>>();
+
+    // 只有一个字符时两种格式看起来一样，不重复列出
+    if text.len() > 1 {
+        let words = text.len().div_ceil(2);
+        for (addr, window) in memory.windows(words).enumerate() {
+            let matches = text.chunks(2).zip(window).all(|(pair, &word)| {
+                let [high, low] = word.to_be_bytes();
+                low == pair[0] && pair.get(1).is_none_or(|&b| b == high)
+            });
+            if matches {
+                found.push((addr as u16, StringEncoding::Putsp));
+            }
+        }
+    }
+
+    found.sort_by_key(|&(addr, _)| addr);
+    found
+}
@@ -0,0 +1,182 @@
+//! 完整虚拟机状态的二进制快照，可以保存下来之后再原样恢复继续运行。
+//! 和state.rs的JSON不同，这里要求一个字都不能丢。
+//!
+//! 格式(全部大端序)：
+//!   4字节魔数 "LC3S"
+//!   u16 版本号
+//!   u16 寄存器个数，后面跟着这么多个u16寄存器值
+//!   u8  running
+//!   u16 saved_usp、u16 saved_ssp
+//!   u32 内存字数(DEVICE_PAGE_START到0x10000之间)，后面跟着这么多个u16内存字
+//! 寄存器个数单独存一份，以后加了寄存器，旧快照会被明确拒绝而不是读错位。
+
+use crate::register::{Reg, Registers};
+use crate::{Vm, DEVICE_PAGE_START, MEMORY_SIZE};
+use std::{fmt, fs, io};
+
+const MAGIC: &[u8; 4] = b"LC3S";
+
+/// 当前的快照格式版本，格式有变化时加1
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// 读快照失败的原因
+#[derive(Debug)]
+pub enum SnapshotError {
+    IoError(io::Error),
+    /// 开头不是"LC3S"，不是快照文件
+    BadMagic,
+    /// 不认识的格式版本
+    UnsupportedVersion(u16),
+    /// 快照里的寄存器个数和当前的虚拟机不一样
+    RegisterCount(usize),
+    /// 文件在某个字段中间就结束了
+    Truncated,
+    /// 内存字数不合理：比设备页以下的程序区还小，或者超过64K
+    MemorySize(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::IoError(e) => write!(f, "{}", e),
+            SnapshotError::BadMagic => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {}", v)
+            }
+            SnapshotError::RegisterCount(n) => write!(
+                f,
+                "snapshot has {} registers, this VM has {}",
+                n,
+                Reg::COUNT as usize
+            ),
+            SnapshotError::Truncated => write!(f, "snapshot file is truncated"),
+            SnapshotError::MemorySize(n) => write!(
+                f,
+                "snapshot memory has {:#x} words, expected {:#x} to {:#x}",
+                n, DEVICE_PAGE_START, MEMORY_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::IoError(e)
+    }
+}
+
+/// 按顺序从字节里取字段，不够了就报Truncated
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], SnapshotError> {
+        if self.data.len() < n {
+            return Err(SnapshotError::Truncated);
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn words(&mut self, n: usize) -> Result<Vec<u16>, SnapshotError> {
+        (0..n).map(|_| self.u16()).collect()
+    }
+}
+
+impl Vm {
+    /// 把内存、寄存器和运行状态编码成快照
+    pub fn snapshot_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16 + 2 * (self.reg.len() + self.memory.len()));
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+
+        out.extend_from_slice(&(self.reg.len() as u16).to_be_bytes());
+        for word in self.reg.iter() {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+
+        out.push(self.running as u8);
+        out.extend_from_slice(&self.saved_usp.to_be_bytes());
+        out.extend_from_slice(&self.saved_ssp.to_be_bytes());
+
+        out.extend_from_slice(&(self.memory.len() as u32).to_be_bytes());
+        for word in &self.memory {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    /// 从快照恢复状态。格式有任何问题时返回错误，虚拟机保持原样
+    pub fn restore_snapshot_bytes(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        let mut reader = Reader { data };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let count = reader.u16()? as usize;
+        if count != Reg::COUNT as usize {
+            return Err(SnapshotError::RegisterCount(count));
+        }
+        let mut reg = Registers::new();
+        for (r, word) in reader.words(count)?.into_iter().enumerate() {
+            reg[r] = word;
+        }
+
+        let running = reader.u8()? != 0;
+        let saved_usp = reader.u16()?;
+        let saved_ssp = reader.u16()?;
+
+        let size = reader.u32()? as usize;
+        if !(DEVICE_PAGE_START as usize..=MEMORY_SIZE).contains(&size) {
+            return Err(SnapshotError::MemorySize(size));
+        }
+        let memory = reader.words(size)?;
+
+        self.reg = reg;
+        self.running = running;
+        self.saved_usp = saved_usp;
+        self.saved_ssp = saved_ssp;
+        self.memory = memory;
+        self.key_buffer = None;
+        // 日志里记的是换掉之前的状态，不能再用来倒退
+        if let Some(journal) = self.journal.as_mut() {
+            journal.clear();
+        }
+        Ok(())
+    }
+
+    /// 把快照写到文件
+    pub fn save_snapshot(&self, path: &str) -> Result<(), SnapshotError> {
+        fs::write(path, self.snapshot_bytes())?;
+        Ok(())
+    }
+
+    /// 从文件恢复快照
+    pub fn load_snapshot(&mut self, path: &str) -> Result<(), SnapshotError> {
+        let data = fs::read(path)?;
+        self.restore_snapshot_bytes(&data)
+    }
+}
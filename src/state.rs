@@ -0,0 +1,60 @@
+//! 停机时机器状态的结构化输出(JSON)，给测试脚本、仪表盘这类工具读取。
+//! 和可重新加载的二进制快照不同，这里只关心人和工具能看懂。
+
+use crate::cond_name;
+use crate::register::{Reg, Registers};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// 一个非零的内存字
+#[derive(Debug, Serialize)]
+pub struct MemoryWord {
+    pub addr: u16,
+    pub value: u16,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MachineState {
+    pub registers: BTreeMap<String, u16>,
+    pub pc: u16,
+    pub cond: &'static str,
+    /// 处理器状态字：特权级别、优先级和条件码
+    pub psr: u16,
+    pub instructions: u64,
+    /// 只记录非零的内存字，程序一般只碰很少的内存，这样文件能小很多
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Vec<MemoryWord>>,
+}
+
+impl MachineState {
+    pub fn capture(reg: &Registers, memory: &[u16], instructions: u64, with_memory: bool) -> Self {
+        let registers = (0..8)
+            .map(|r| (format!("R{}", r), reg[r]))
+            .collect::<BTreeMap<_, _>>();
+
+        let memory = with_memory.then(|| {
+            memory
+                .iter()
+                .enumerate()
+                .filter(|(_, &value)| value != 0)
+                .map(|(addr, &value)| MemoryWord {
+                    addr: addr as u16,
+                    value,
+                })
+                .collect()
+        });
+
+        MachineState {
+            registers,
+            pc: reg[Reg::PC],
+            cond: cond_name(reg[Reg::COND]),
+            psr: reg[Reg::PSR],
+            instructions,
+            memory,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("machine state is always serializable")
+    }
+}
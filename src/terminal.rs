@@ -0,0 +1,112 @@
+//! 终端的原始模式。
+//! 标准控制台的默认行为是按行缓冲并回显输入，只有按下回车之后程序才能读到，
+//! 玩游戏时需要按一个键就立即交给程序、而且不回显，所以运行期间要切换成原始模式。
+//! RawMode是一个RAII guard：创建时切换，drop时恢复原来的设置。
+//! Unix下用termios关掉ICANON和ECHO，Windows下用console API关掉行输入和回显。
+
+use std::io;
+
+/// 终端处于原始模式期间持有的guard，drop时恢复原来的设置
+pub struct RawMode {
+    inner: platform::Saved,
+}
+
+impl RawMode {
+    /// 把stdin所在的终端切换成原始模式。
+    /// stdin不是终端(比如从管道或文件输入)时返回错误，这种情况下本来也不需要切换
+    pub fn enable() -> io::Result<RawMode> {
+        let inner = platform::Saved::capture()?;
+        inner.apply_raw()?;
+        Ok(RawMode { inner })
+    }
+
+    /// 临时恢复成原来的模式，比如调试器按行读命令的时候
+    pub fn suspend(&self) -> io::Result<()> {
+        self.inner.apply_original()
+    }
+
+    /// suspend之后重新切回原始模式
+    pub fn resume(&self) -> io::Result<()> {
+        self.inner.apply_raw()
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // drop里没办法再往上报错了，恢复失败也只能忽略
+        let _ = self.inner.apply_original();
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::io;
+    use termios::*;
+
+    const STDIN: i32 = 0;
+
+    pub struct Saved {
+        original: Termios,
+        raw: Termios,
+    }
+
+    impl Saved {
+        pub fn capture() -> io::Result<Saved> {
+            let original = Termios::from_fd(STDIN)?;
+            let mut raw = original;
+            // Refer: https://stackoverflow.com/questions/26321592/how-can-i-read-one-character-from-stdin-without-having-to-hit-enter
+            raw.c_iflag &= IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON;
+            raw.c_lflag &= !(ICANON | ECHO); // no echo and canonical mode
+            Ok(Saved { original, raw })
+        }
+
+        pub fn apply_raw(&self) -> io::Result<()> {
+            tcsetattr(STDIN, TCSANOW, &self.raw)
+        }
+
+        pub fn apply_original(&self) -> io::Result<()> {
+            tcsetattr(STDIN, TCSANOW, &self.original)
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+    use windows_sys::Win32::Foundation::HANDLE;
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE, ENABLE_ECHO_INPUT,
+        ENABLE_LINE_INPUT, STD_INPUT_HANDLE,
+    };
+
+    pub struct Saved {
+        handle: HANDLE,
+        original: CONSOLE_MODE,
+    }
+
+    impl Saved {
+        pub fn capture() -> io::Result<Saved> {
+            let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+            let mut original: CONSOLE_MODE = 0;
+            if unsafe { GetConsoleMode(handle, &mut original) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Saved { handle, original })
+        }
+
+        pub fn apply_raw(&self) -> io::Result<()> {
+            self.set(self.original & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT))
+        }
+
+        pub fn apply_original(&self) -> io::Result<()> {
+            self.set(self.original)
+        }
+
+        fn set(&self, mode: CONSOLE_MODE) -> io::Result<()> {
+            if unsafe { SetConsoleMode(self.handle, mode) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+}
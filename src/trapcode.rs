@@ -2,65 +2,247 @@
 /// 它们实际上并未向 LC-3 引入任何新功能，它们只是提供了一种执行任务的便捷方法（类似于操作系统系统调用）。
 /// 在官方的 LC-3 模拟器中，陷阱例程是用汇编语言编写的。当调用陷阱代码时，被PC移动到该代码的地址。
 /// CPU 执行过程的指令，完成后，PC重置到初始调用后的位置。
-use lc_3_vm::register::Reg;
-use std::io::Read;
-
-/// get character from keyboard, not echoed onto the terminal
-pub fn trap_getc(reg: &mut Vec<u16>) {
-    let mut buffer = [0 as u8; 1];
-    std::io::stdin().read_exact(&mut buffer).unwrap();
-    reg[Reg::R0] = buffer[0].into();
+use crate::register::{Reg, Registers};
+use crate::{to_signed, with_vm, Vm, DEVICE_PAGE_START};
+use std::ops::Range;
+
+/// PUTS/PUTSP最多输出的字数，超过了还没遇到结尾的0就当作字符串没有结束符
+pub const MAX_STRING_WORDS: usize = 0x1000;
+
+/// --log-input时把交给程序的每个字节打到stderr，格式如 GETC@3012 -> 0x0A '\n'
+/// PC此时已经指向下一条指令，所以trap(或者读KBDR的指令)本身的地址要减1
+pub(crate) fn log_input(trap: &str, reg: &Registers, byte: u8) {
+    eprintln!(
+        "{}@{:04X} -> {:#04X} {:?}",
+        trap,
+        reg[Reg::PC].wrapping_sub(1),
+        byte,
+        byte as char
+    );
 }
 
-/// output a character
-pub fn trap_out(reg: &mut Vec<u16>) {
-    print!("{}", (reg[Reg::R0] as u8) as char);
+/// 同Vm::trap_getc，没有Vm上的键缓冲，直接阻塞读一个键
+pub fn trap_getc(reg: &mut Registers, log: bool) {
+    with_vm(reg, &mut vec![], |vm| {
+        vm.log_input = log;
+        vm.trap_getc();
+    });
 }
 
-/// output a word string
-pub fn trap_puts(reg: &mut Vec<u16>, memory: &mut Vec<u16>) {
-    let mut index = reg[Reg::R0] as usize;
+/// 同Vm::trap_out，没有Vm上的内存，不会镜像到DDR
+pub fn trap_out(reg: &mut Registers, ascii_only: bool) {
+    with_vm(reg, &mut vec![], |vm| {
+        vm.ascii_only = ascii_only;
+        vm.trap_out();
+    });
+}
 
-    while index < memory.len() && memory[index] != 0 {
-        print!("{}", (memory[index] as u8) as char);
-        index = index + 1;
-    }
+/// 在没有内存的临时Vm上输出bytes，给下面两个自由函数用
+fn emit_bytes(reg: &mut Registers, bytes: &[u8], ascii_only: bool) {
+    with_vm(reg, &mut vec![], |vm| {
+        vm.ascii_only = ascii_only;
+        vm.emit_bytes(bytes);
+    });
 }
 
-/// get character from keyboard, echoed onto the terminal
-pub fn trap_in(reg: &mut Vec<u16>) {
-    print!("Enter a character: ");
+/// 从start开始找字符串结尾的0，返回字符串占用的范围(不含0)和是否真的找到了0。
+/// 最多找MAX_STRING_WORDS个字，也不会越过内存末尾或者读进设备区
+fn string_range(memory: &[u16], start: usize) -> (Range<usize>, bool) {
+    let limit = memory
+        .len()
+        .min(DEVICE_PAGE_START as usize)
+        .min(start + MAX_STRING_WORDS);
+    let start = start.min(limit);
 
-    reg[Reg::R0] = std::io::stdin()
-        .bytes()
-        .next()
-        .and_then(|result| result.ok())
-        .map(|byte| byte as u16)
-        .unwrap();
+    match (start..limit).find(|&i| memory[i] == 0) {
+        Some(end) => (start..end, true),
+        None => (start..limit, false),
+    }
 }
 
-/// output a byte string
-pub fn trap_putsp(reg: &mut Vec<u16>, memory: &mut Vec<u16>) {
-    let mut index = reg[Reg::R0] as usize;
+/// PUTS要输出的字节：每个字的低8位是一个字符，高8位不管是什么都不输出。
+/// 第二个返回值为false表示没有找到结尾的0，输出被截断了
+pub fn puts_bytes(memory: &[u16], addr: u16) -> (Vec<u8>, bool) {
+    let (range, terminated) = string_range(memory, addr as usize);
+    let bytes = memory[range].iter().map(|&word| word as u8).collect();
+    (bytes, terminated)
+}
 
-    while index < memory.len() && memory[index] != 0 {
-        //A word in our VM is 16 bits
-        let word: u16 = memory[index];
+/// 同Vm::trap_puts，输出的字节由puts_bytes算出来。
+/// 返回false表示没有找到结尾的0，输出被截断了，这里不打警告
+pub fn trap_puts(reg: &mut Registers, memory: &[u16], ascii_only: bool) -> bool {
+    let (bytes, terminated) = puts_bytes(memory, reg[Reg::R0]);
+    emit_bytes(reg, &bytes, ascii_only);
+    terminated
+}
 
-        //We get the two bytes from our word. bytes here is an array of u8
-        let bytes = word.to_be_bytes();
+/// 同Vm::trap_in，没有Vm上的键缓冲，直接阻塞读一个键
+pub fn trap_in(reg: &mut Registers, log: bool) {
+    with_vm(reg, &mut vec![], |vm| {
+        vm.log_input = log;
+        vm.trap_in();
+    });
+}
 
-        print!("{}", bytes[1] as char);
+/// 同Vm::trap_putsp，返回值和trap_puts一样
+pub fn trap_putsp(reg: &mut Registers, memory: &[u16], ascii_only: bool) -> bool {
+    let (bytes, terminated) = putsp_bytes(memory, reg[Reg::R0]);
+    emit_bytes(reg, &bytes, ascii_only);
+    terminated
+}
 
-        if bytes[0] != 0 {
-            print!("{}", bytes[0] as char);
+/// PUTSP要输出的字节：每个字先低8位、再高8位各存一个字符。
+/// 和官方OS的PUTSP一样，全0的字结束字符串，字里面遇到0字节也在那里结束：
+/// 奇数长度的字符串最后一个字是0x00XX，只输出低8位；低8位是0的字一个字节都不输出。
+/// 第二个返回值的含义和puts_bytes一样
+pub fn putsp_bytes(memory: &[u16], addr: u16) -> (Vec<u8>, bool) {
+    let (range, terminated) = string_range(memory, addr as usize);
+    let mut bytes = vec![];
+    for &word in &memory[range] {
+        //We get the two bytes from our word. bytes here is an array of u8
+        let [high, low] = word.to_be_bytes();
+        if low == 0 {
+            return (bytes, true);
         }
-
-        index = index + 1;
+        bytes.push(low);
+        if high == 0 {
+            return (bytes, true);
+        }
+        bytes.push(high);
     }
+    (bytes, terminated)
+}
+
+/// 字符串没有结束符时提醒一下，免得把一大片内存当成字符串打出来还不知道为什么
+fn warn_unterminated(trap: &str, reg: &Registers) {
+    eprintln!(
+        "Warning: {} string at {:#06x} has no terminator within {} words, output truncated",
+        trap,
+        reg[Reg::R0],
+        MAX_STRING_WORDS
+    );
 }
 
 /// halt the program
+/// 停机提示写到stderr，不和程序自己的输出混在一起
 pub fn trap_halt() {
-    println!("HALT Trapcode received, Halting.");
+    eprintln!("HALT Trapcode received, Halting.");
+}
+
+/// Vm上的trap方法，输入日志的开关直接从Vm上取；
+/// 读键盘的trap要和KBSR轮询共用Vm上的键缓冲，输出的trap要和写DDR共用Vm::emit_char，
+/// 所以直接实现成方法
+impl Vm {
+    /// get character from keyboard, not echoed onto the terminal
+    /// 输入读完时R0是EOF_SENTINEL，见Vm::end_of_input
+    pub fn trap_getc(&mut self) {
+        self.reg[Reg::R0] = match self.read_key() {
+            Some(byte) => {
+                if self.log_input {
+                    log_input("GETC", &self.reg, byte);
+                }
+                byte.into()
+            }
+            None => self.end_of_input(),
+        };
+    }
+
+    /// output a character
+    pub fn trap_out(&mut self) {
+        self.emit_char(self.reg[Reg::R0] as u8);
+    }
+
+    /// output a word string
+    pub fn trap_puts(&mut self) {
+        let size = self.memory_size();
+        let (bytes, terminated) = puts_bytes(&self.memory[..size], self.reg[Reg::R0]);
+        self.emit_bytes(&bytes);
+        if !terminated {
+            warn_unterminated("PUTS", &self.reg);
+        }
+    }
+
+    /// get character from keyboard, echoed onto the terminal
+    /// 提示语要在阻塞读之前flush出来，读到的字符和真正的IN一样回显到显示器上
+    pub fn trap_in(&mut self) {
+        self.emit_bytes(b"Enter a character: ");
+        self.flush_output();
+
+        self.reg[Reg::R0] = match self.read_key() {
+            Some(byte) => {
+                if self.log_input {
+                    log_input("IN", &self.reg, byte);
+                }
+                self.emit_char(byte);
+                self.flush_output();
+                byte.into()
+            }
+            None => self.end_of_input(),
+        };
+    }
+
+    /// output a byte string
+    pub fn trap_putsp(&mut self) {
+        let size = self.memory_size();
+        let (bytes, terminated) = putsp_bytes(&self.memory[..size], self.reg[Reg::R0]);
+        self.emit_bytes(&bytes);
+        if !terminated {
+            warn_unterminated("PUTSP", &self.reg);
+        }
+    }
+
+    /// 扩展trap READLINE(0x26)：从键盘读一行，每个字符一个字(和PUTS的格式一样)存到R0开始的地方，
+    /// 最多读R1个字符，读到换行为止(换行不存)，后面补一个0，所以缓冲区要有R1+1个字。
+    /// 读到的字符数放回R1。和IN一样把输入的字符回显出来。
+    /// 一个字符都没读到输入就完了时按on_eof处理(见Vm::end_of_input)，R1是EOF_SENTINEL(0xFFFF)，
+    /// 和GETC/IN一样，这样程序能区分输入结束和只按了回车的空行(R1是0)
+    pub fn trap_readline(&mut self) {
+        let buffer = self.reg[Reg::R0];
+        let max = self.reg[Reg::R1];
+        self.flush_output();
+
+        let mut count: u16 = 0;
+        let mut eof = false;
+        while count < max {
+            let Some(byte) = self.read_key() else {
+                eof = count == 0;
+                break;
+            };
+            if self.log_input {
+                log_input("READLINE", &self.reg, byte);
+            }
+            self.emit_char(byte);
+            self.flush_output();
+            if byte == b'\n' || byte == b'\r' {
+                break;
+            }
+            self.mem_write(buffer.wrapping_add(count), byte.into());
+            count += 1;
+        }
+
+        self.mem_write(buffer.wrapping_add(count), 0);
+        self.reg[Reg::R1] = if eof { self.end_of_input() } else { count };
+    }
+
+    /// 扩展trap PUTINT(0x27)：把R0当成有符号数按十进制输出，负数前面带'-'，不换行。
+    /// 调试时用来代替一位一位地拼数字
+    pub fn trap_putint(&mut self) {
+        let text = to_signed(self.reg[Reg::R0]).to_string();
+        self.emit_bytes(text.as_bytes());
+    }
+
+    /// 扩展trap RAND(0x28)：R0 = 下一个伪随机数(0-0xFFFF)，算法见rng.rs。
+    /// 用--seed固定种子时每次运行得到的序列都一样
+    pub fn trap_rand(&mut self) {
+        self.reg[Reg::R0] = self.rng.next_u16();
+    }
+
+    pub fn trap_halt(&mut self) {
+        // 程序最后一行输出不能丢，也要排在停机提示前面
+        self.flush_output();
+        if !self.quiet {
+            trap_halt();
+        }
+        self.running = false;
+    }
 }
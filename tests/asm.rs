@@ -0,0 +1,147 @@
+use lc_3_vm::asm::{assemble, assemble_with_listing, to_bytes, AsmError};
+use lc_3_vm::inline_asm::LineError;
+use lc_3_vm::{read_image_from_bytes, MEMORY_SIZE};
+
+#[test]
+fn emits_origin_then_words() {
+    let source = "
+        .ORIG x3000
+        AND R0, R0, #0   ; clear
+        ADD R0, R0, #5
+        BRp #-2
+        HALT
+        .FILL xBEEF
+        .END
+        ADD R0, R0, #1
+    ";
+    assert_eq!(
+        assemble(source),
+        Ok(vec![0x3000, 0x5020, 0x1025, 0x03FE, 0xF025, 0xBEEF])
+    );
+}
+
+#[test]
+fn output_loads_as_an_image() {
+    let words = assemble(".orig x4000\nLEA R0, #1\nPUTS\n").unwrap();
+    let mut memory = vec![0u16; MEMORY_SIZE];
+
+    let range = read_image_from_bytes(&to_bytes(&words), None, &mut memory).unwrap();
+    assert_eq!(range, 0x4000..0x4002);
+    assert_eq!(memory[0x4000], 0xE001);
+    assert_eq!(memory[0x4001], 0xF022);
+}
+
+#[test]
+fn reports_errors_with_line_numbers() {
+    assert_eq!(
+        assemble("ADD R0, R0, #1"),
+        Err(AsmError::MissingOrig { line: 1 })
+    );
+    assert_eq!(
+        assemble(".ORIG x3000\n.ORIG x4000"),
+        Err(AsmError::BadOrig {
+            line: 2,
+            token: "x4000".to_string()
+        })
+    );
+    assert_eq!(
+        assemble(".ORIG x3000\n\nADD R0, R0, #99"),
+        Err(AsmError::Line {
+            line: 3,
+            error: LineError::OutOfRange { value: 99, bits: 5 }
+        })
+    );
+}
+
+#[test]
+fn resolves_labels_in_two_passes() {
+    let source = r#"
+        .ORIG x3000
+        LEA R0, MSG
+        PUTS
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        BRnzp DONE      ; forward reference
+        .BLKW 2
+DONE:   LD R2, PTR
+        HALT
+PTR     .FILL MSG
+MSG     .STRINGZ "a;\"b\n"
+        .END
+    "#;
+    let words = assemble(source).unwrap();
+    assert_eq!(
+        words,
+        vec![
+            0x3000,
+            0xE009,
+            0xF022,
+            0x127F,
+            0x03FE,
+            0x0E02,
+            0,
+            0,
+            0x2401,
+            0xF025,
+            0x300A,
+            b'a' as u16,
+            b';' as u16,
+            b'"' as u16,
+            b'b' as u16,
+            b'\n' as u16,
+            0,
+        ]
+    );
+}
+
+#[test]
+fn reports_label_errors() {
+    assert_eq!(
+        assemble(".ORIG x3000\nBRz NOWHERE"),
+        Err(AsmError::Line {
+            line: 2,
+            error: LineError::UnresolvedLabel("NOWHERE".to_string())
+        })
+    );
+    assert_eq!(
+        assemble(".ORIG x3000\nA ADD R0, R0, #1\nA HALT"),
+        Err(AsmError::DuplicateLabel {
+            line: 3,
+            label: "A".to_string()
+        })
+    );
+    assert_eq!(
+        assemble(".ORIG x3000\nBR FAR\n.BLKW 300\nFAR HALT"),
+        Err(AsmError::LabelOutOfRange {
+            line: 2,
+            label: "FAR".to_string(),
+            offset: 300
+        })
+    );
+}
+
+#[test]
+fn lists_source_with_addresses_and_symbols() {
+    let source = ".ORIG x3000\nSTART LEA R0, MSG ; greet\n  PUTS\nMSG .STRINGZ \"hi\"\n.END";
+    let (words, symbols, listing) = assemble_with_listing(source).unwrap();
+
+    assert_eq!(words, assemble(source).unwrap());
+    assert_eq!(symbols.address("START"), Some(0x3000));
+    assert_eq!(symbols.address("MSG"), Some(0x3002));
+    assert_eq!(symbols.label_at(0x3002), Some("MSG"));
+    assert_eq!(symbols.address("NOPE"), None);
+    assert_eq!(
+        symbols.to_text(),
+        "START                0x3000\nMSG                  0x3002\n"
+    );
+    assert_eq!(
+        listing,
+        "                .ORIG x3000\n\
+         0x3000  0xe001  START LEA R0, MSG ; greet\n\
+         0x3001  0xf022    PUTS\n\
+         0x3002  0x0068  MSG .STRINGZ \"hi\"\n\
+         0x3003  0x0069\n\
+         0x3004  0x0000\n\
+         \x20               .END\n"
+    );
+}
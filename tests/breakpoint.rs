@@ -0,0 +1,47 @@
+use lc_3_vm::breakpoint::{split_spec, CompareOp, Condition};
+use lc_3_vm::new_registers;
+use lc_3_vm::register::Reg;
+
+#[test]
+fn parses_register_conditions() {
+    assert_eq!(
+        Condition::parse("R2 == 0xFFFF"),
+        Some(Condition {
+            reg: 2,
+            op: CompareOp::Eq,
+            value: 0xFFFF
+        })
+    );
+    assert_eq!(
+        Condition::parse("pc >= x3010").map(|c| (c.reg, c.op, c.value)),
+        Some((Reg::PC as usize, CompareOp::Ge, 0x3010))
+    );
+    assert_eq!(Condition::parse("r0 < #-1").unwrap().value, 0xFFFF);
+
+    assert_eq!(Condition::parse("R8 == 1"), None);
+    assert_eq!(Condition::parse("R1 = 1"), None);
+    assert_eq!(Condition::parse("R1 == 0x10000"), None);
+    assert_eq!(Condition::parse("R1 =="), None);
+}
+
+#[test]
+fn compares_signed_for_ordering() {
+    let mut reg = new_registers();
+    reg[Reg::R2] = 0xFFFF;
+
+    assert!(Condition::parse("R2 == 0xFFFF").unwrap().holds(&reg));
+    assert!(Condition::parse("R2 == -1").unwrap().holds(&reg));
+    assert!(Condition::parse("R2 < 0").unwrap().holds(&reg));
+    assert!(!Condition::parse("R2 > 0").unwrap().holds(&reg));
+    assert!(Condition::parse("R3 != 5").unwrap().holds(&reg));
+}
+
+#[test]
+fn splits_address_from_condition() {
+    let (addr, condition) = split_spec("0x3010 if R2 == 0xFFFF").unwrap();
+    assert_eq!(addr, "0x3010");
+    assert_eq!(condition.unwrap().reg, 2);
+
+    assert_eq!(split_spec("LOOP"), Some(("LOOP", None)));
+    assert_eq!(split_spec("0x3010 if R2 ~ 1"), None);
+}
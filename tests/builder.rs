@@ -0,0 +1,126 @@
+use lc_3_vm::asm::{assemble, to_bytes};
+use lc_3_vm::builder::{BuildError, VmBuilder};
+use lc_3_vm::register::Reg;
+use lc_3_vm::{ImageError, VmError};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// 交给builder之后还能从外面看到写了什么
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn image(source: &str) -> Vec<u8> {
+    to_bytes(&assemble(source).unwrap())
+}
+
+#[test]
+fn builds_a_configured_vm_and_runs_it() {
+    let data = image(".ORIG x3000\nGETC\nOUT\nGETC\nOUT\nHALT\n.END\n");
+    let output = SharedOutput::default();
+
+    let mut vm = VmBuilder::new()
+        .origin(0x3000)
+        .image_bytes(&data)
+        .max_instructions(100_000)
+        .input(&b"hi"[..])
+        .output(output.clone())
+        .build()
+        .unwrap();
+    vm.run().unwrap();
+
+    assert_eq!(*output.0.borrow(), b"hi");
+    assert!(!vm.running);
+}
+
+#[test]
+fn origin_defaults_to_the_first_image() {
+    let data = image(".ORIG x4000\nHALT\n.END\n");
+    let vm = VmBuilder::new().image_bytes(&data).build().unwrap();
+    assert_eq!(vm.reg[Reg::PC], 0x4000);
+    assert_eq!(vm.origin, 0x4000);
+}
+
+#[test]
+fn max_instructions_stops_a_runaway_program() {
+    let data = image(".ORIG x3000\nLOOP ADD R0, R0, #0\nBRnzp LOOP\n.END\n");
+    let mut vm = VmBuilder::new()
+        .image_bytes(&data)
+        .max_instructions(10)
+        .output(io::sink())
+        .build()
+        .unwrap();
+
+    match vm.run() {
+        Err(VmError::InstructionLimit { pc, limit }) => {
+            assert_eq!(pc, 0x3000);
+            assert_eq!(limit, 10);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn rejects_images_that_do_not_fit() {
+    let data = image(".ORIG x3000\n.BLKW 8\n.END\n");
+    match VmBuilder::new()
+        .memory_size(0x3004)
+        .image_bytes(&data)
+        .build()
+    {
+        Err(BuildError::Image {
+            index: 0,
+            error: ImageError::TooLarge { origin, words },
+        }) => {
+            assert_eq!(origin, 0x3000);
+            assert_eq!(words, 8);
+        }
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("image should not fit"),
+    }
+}
+
+#[test]
+fn rejects_overlapping_images() {
+    let first = image(".ORIG x3000\n.BLKW 4\n.END\n");
+    let second = image(".ORIG x3002\nHALT\n.END\n");
+    match VmBuilder::new()
+        .image_bytes(&first)
+        .image_bytes(&second)
+        .build()
+    {
+        Err(BuildError::Overlap {
+            first: 0,
+            second: 1,
+            range,
+        }) => assert_eq!(range, 0x3002..0x3003),
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("overlap should be rejected"),
+    }
+}
+
+#[test]
+fn rejects_origin_outside_program_memory() {
+    for (memory_size, origin) in [(0x1000, 0x3000), (0x10000, 0xFE00)] {
+        match VmBuilder::new()
+            .memory_size(memory_size)
+            .origin(origin)
+            .build()
+        {
+            Err(BuildError::BadOrigin { origin: o, .. }) => assert_eq!(o, origin),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("origin {:#06x} should be rejected", origin),
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use lc_3_vm::capabilities::capabilities;
+
+#[test]
+fn reports_builtin_traps_and_devices() {
+    let caps = capabilities();
+    assert_eq!(caps.traps.first(), Some(&(0x20, "GETC")));
+    assert_eq!(caps.traps.last(), Some(&(0x25, "HALT")));
+
+    let report = caps.report();
+    assert!(report.starts_with(&format!("lc-3_vm {}\n", env!("CARGO_PKG_VERSION"))));
+    assert!(report.contains(
+        "Traps: GETC (0x20), OUT (0x21), PUTS (0x22), IN (0x23), PUTSP (0x24), HALT (0x25)\n"
+    ));
+    assert!(report
+        .contains("Extension traps (--extensions): READLINE (0x26), PUTINT (0x27), RAND (0x28)\n"));
+    assert!(report.contains("Interrupts: yes\n"));
+}
@@ -0,0 +1,102 @@
+//! 直接运行编译出来的lc-3_vm，检查命令行选项在stderr/stdout上的效果
+
+use lc_3_vm::asm::{assemble, to_bytes};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// 把程序汇编成.obj、输入写成文件，放在这个测试自己的临时目录里
+fn fixture(name: &str, source: &str, input: &[u8]) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("lc3_cli_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let image = dir.join("program.obj");
+    std::fs::write(&image, to_bytes(&assemble(source).unwrap())).unwrap();
+    let script = dir.join("input.txt");
+    std::fs::write(&script, input).unwrap();
+    (image, script)
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lc-3_vm"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn log_input_reports_keys_read_from_kbdr() {
+    let source = "
+        .orig x3000
+WAIT    LDI R0, KBSR
+        BRzp WAIT
+        LDI R0, KBDR
+        HALT
+KBSR    .fill xFE00
+KBDR    .fill xFE02
+        .end
+";
+    let (image, script) = fixture("log_input", source, b"k");
+    let output = run(&[
+        "--quiet",
+        "--log-input",
+        "--input-script",
+        script.to_str().unwrap(),
+        image.to_str().unwrap(),
+    ]);
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("KBDR@3002 -> 0x6B 'k'"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn debugger_commands_and_program_input_share_piped_stdin() {
+    let source = "
+        .orig x3000
+        GETC
+        OUT
+        HALT
+        .end
+";
+    let (image, _) = fixture("debug_stdin", source, b"");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lc-3_vm"))
+        .args(["--quiet", "--debug", image.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // 第一行是调试器的命令，后面的x是留给GETC的
+    child.stdin.take().unwrap().write_all(b"c\nx").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"x");
+}
+
+#[test]
+fn prompts_quit_when_stdin_is_at_eof() {
+    let source = "
+        .orig x3000
+        ADD R1, R1, #1
+        HALT
+        .end
+";
+    let (image, _) = fixture("prompt_eof", source, b"");
+    let image = image.to_str().unwrap();
+
+    // run的stdin是空的：断点的"按任意键"和调试器的命令行一读就是EOF
+    for args in [
+        &["--quiet", "--break", "0x3000", image][..],
+        &["--quiet", "--debug", image][..],
+    ] {
+        let output = run(args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{:?}: {}", args, stderr);
+        assert!(!stderr.contains("panicked"), "{:?}: {}", args, stderr);
+    }
+}
@@ -0,0 +1,17 @@
+//! 各个集成测试共用的小工具。每个测试文件只用到其中一部分
+#![allow(dead_code)]
+
+use lc_3_vm::inline_asm::assemble_line;
+use lc_3_vm::Vm;
+
+/// 汇编一行指令
+pub fn word(line: &str) -> u16 {
+    assemble_line(line).unwrap().unwrap()
+}
+
+/// 把几行汇编逐行汇编好，从origin开始放进内存
+pub fn load(vm: &mut Vm, origin: usize, lines: &[&str]) {
+    for (i, line) in lines.iter().enumerate() {
+        vm.memory[origin + i] = word(line);
+    }
+}
@@ -0,0 +1,164 @@
+//! 一组容易出问题的指令字：全0、全1、每个opcode偏移量的边界、保留的opcode、
+//! 每个trap vector、保留位不是0的写法。每个字都放在一台新虚拟机的0x3000上
+//! 解码并执行一次，不能panic，结果要和下面写的一致。随机fuzz找到新的问题时把那个字加进来
+
+use lc_3_vm::opcodes::decode_opcode;
+use lc_3_vm::register::Reg;
+use lc_3_vm::{Cond, StepResult, TrapCode, Vm};
+
+/// 执行一次之后应该看到的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    /// 正常执行完，PC是这个值
+    Next(u16),
+    Halted,
+    /// 保留的opcode、RTI、没有定义的trap
+    Bad,
+}
+
+use Expect::*;
+
+const CORPUS: &[(u16, Expect)] = &[
+    // BR：nzp全0的BR什么都不做，全1的PCoffset9是-1
+    (0x0000, Next(0x3001)),
+    (0x0E00, Next(0x3001)),
+    (0x0FFF, Next(0x3000)),
+    (0x0EFF, Next(0x3100)),
+    (0x0F00, Next(0x2F01)),
+    // ADD/AND：imm5的两端，寄存器模式下[4:3]不是0
+    (0x1000, Next(0x3001)),
+    (0x102F, Next(0x3001)),
+    (0x1030, Next(0x3001)),
+    (0x1018, Next(0x3001)),
+    (0x5020, Next(0x3001)),
+    (0x5FFF, Next(0x3001)),
+    (0x5018, Next(0x3001)),
+    // LD/ST/LDI/STI/LEA：PCoffset9的两端
+    (0x2000, Next(0x3001)),
+    (0x20FF, Next(0x3001)),
+    (0x2100, Next(0x3001)),
+    (0x21FF, Next(0x3001)),
+    (0x3000, Next(0x3001)),
+    (0x30FF, Next(0x3001)),
+    (0x3100, Next(0x3001)),
+    (0x31FF, Next(0x3001)),
+    (0xA000, Next(0x3001)),
+    (0xA0FF, Next(0x3001)),
+    (0xA100, Next(0x3001)),
+    (0xB000, Next(0x3001)),
+    (0xB1FF, Next(0x3001)),
+    (0xE000, Next(0x3001)),
+    (0xE0FF, Next(0x3001)),
+    (0xE100, Next(0x3001)),
+    // JSR：PCoffset11的两端；JSRR的[5:0]不是0
+    (0x4800, Next(0x3001)),
+    (0x4BFF, Next(0x3400)),
+    (0x4C00, Next(0x2C01)),
+    (0x4FFF, Next(0x3000)),
+    (0x4000, Next(0x0000)),
+    (0x403F, Next(0x0000)),
+    // LDR/STR：offset6的两端，地址回绕
+    (0x6000, Next(0x3001)),
+    (0x601F, Next(0x3001)),
+    (0x6020, Next(0x3001)),
+    (0x7000, Next(0x3001)),
+    (0x703F, Next(0x3001)),
+    // NOT：[5:0]应该全是1，不是的时候也照样取反
+    (0x903F, Next(0x3001)),
+    (0x9000, Next(0x3001)),
+    // JMP/RET：[11:9]和[5:0]不是0
+    (0xC000, Next(0x0000)),
+    (0xC1C0, Next(0x0000)),
+    (0xCE3F, Next(0x0000)),
+    // 程序一直在用户模式下运行，RTI是privilege mode violation，不管低位是什么
+    (0x8000, Bad),
+    (0x8FFF, Bad),
+    // RES
+    (0xD000, Bad),
+    (0xDFFF, Bad),
+    // TRAP：[11:8]不是0时只看低8位；全1是没有定义的xFF
+    (0xF025, Halted),
+    (0xFF25, Halted),
+    (0xFFFF, Bad),
+];
+
+/// 新虚拟机执行word一次。没有输入，GETC/IN读到EOF
+fn execute(word: u16) -> (Vm, StepResult) {
+    let mut vm = Vm::new();
+    vm.set_input(std::io::empty());
+    // 新虚拟机的COND是0，BRnzp也不会跳；设成Z，BR的偏移量才测得到
+    vm.reg[Reg::COND] = Cond::FL_ZRO.bits();
+    vm.memory[0x3000] = word;
+    let result = vm.step_once();
+    (vm, result)
+}
+
+fn check(word: u16, expect: Expect) {
+    assert_eq!(
+        decode_opcode(word) as u16,
+        word >> 12,
+        "decode {:#06x}",
+        word
+    );
+
+    let (vm, result) = execute(word);
+    match expect {
+        Next(pc) => {
+            assert_eq!(result, StepResult::Continued, "{:#06x}", word);
+            assert_eq!(vm.reg[Reg::PC], pc, "PC after {:#06x}", word);
+            assert!(vm.running, "{:#06x}", word);
+        }
+        Halted => {
+            assert_eq!(result, StepResult::Halted, "{:#06x}", word);
+            assert!(!vm.running, "{:#06x}", word);
+        }
+        Bad => assert_eq!(
+            result,
+            StepResult::BadInstruction {
+                pc: 0x3000,
+                instr: word
+            },
+            "{:#06x}",
+            word
+        ),
+    }
+}
+
+#[test]
+fn corpus_words_decode_and_execute_sensibly() {
+    for &(word, expect) in CORPUS {
+        check(word, expect);
+    }
+}
+
+#[test]
+fn every_opcode_with_all_operand_bits_clear_and_set() {
+    for opcode in 0..16u16 {
+        for word in [opcode << 12, opcode << 12 | 0x0FFF] {
+            assert_eq!(decode_opcode(word) as u16, opcode);
+            let (_, result) = execute(word);
+            assert!(
+                matches!(
+                    result,
+                    StepResult::Continued | StepResult::Halted | StepResult::BadInstruction { .. }
+                ),
+                "{:#06x}: {:?}",
+                word,
+                result
+            );
+        }
+    }
+}
+
+#[test]
+fn every_trap_vector() {
+    for vector in 0..=0xFFu16 {
+        let word = 0xF000 | vector;
+        let expect = match vector {
+            v if v == TrapCode::HALT as u16 => Halted,
+            0x20..=0x24 => Next(0x3001),
+            _ => Bad,
+        };
+        check(word, expect);
+    }
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use common::load;
+use lc_3_vm::device::{MapError, MmioDevice};
+use lc_3_vm::register::Reg;
+use lc_3_vm::{StepResult, Vm};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// 设备收到的每次写入(偏移, 值)
+type WriteLog = Rc<RefCell<Vec<(u16, u16)>>>;
+
+/// 假设备：偏移0每读一次加1，偏移1记下最后写进来的值
+struct Counter {
+    count: u16,
+    written: WriteLog,
+}
+
+impl MmioDevice for Counter {
+    fn read(&mut self, offset: u16) -> u16 {
+        if offset == 0 {
+            self.count += 1;
+            self.count
+        } else {
+            0xBEEF
+        }
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        self.written.borrow_mut().push((offset, val));
+    }
+}
+
+fn counter() -> (Box<Counter>, WriteLog) {
+    let written = Rc::new(RefCell::new(vec![]));
+    let device = Counter {
+        count: 0,
+        written: written.clone(),
+    };
+    (Box::new(device), written)
+}
+
+/// 把汇编行依次放到0x3000开始的内存里，一直执行到HALT
+fn run(vm: &mut Vm, lines: &[&str]) {
+    load(vm, 0x3000, lines);
+    while vm.step_once() == StepResult::Continued {}
+}
+
+#[test]
+fn program_reads_and_writes_a_mapped_device() {
+    let mut vm = Vm::new();
+    let (device, written) = counter();
+    vm.map_device(0xFE10..=0xFE11, device).unwrap();
+    run(
+        &mut vm,
+        &[
+            "LDI R0, #4",
+            "LDI R0, #3",
+            "LDI R1, #3",
+            "STI R0, #2",
+            "HALT",
+            ".FILL xFE10",
+            ".FILL xFE11",
+        ],
+    );
+
+    assert_eq!(vm.reg[Reg::R0], 2);
+    assert_eq!(vm.reg[Reg::R1], 0xBEEF);
+    assert_eq!(*written.borrow(), vec![(1, 2)]);
+    // 设备自己保存状态，内存里的那个字没有变
+    assert_eq!(vm.memory[0xFE11], 0);
+}
+
+#[test]
+fn device_can_cover_ordinary_memory() {
+    let mut vm = Vm::new();
+    vm.memory[0x4000] = 0x1111;
+    vm.reg[Reg::R4] = 0x4000;
+    let (device, written) = counter();
+    vm.map_device(0x4000..=0x4001, device).unwrap();
+    run(
+        &mut vm,
+        &["LDR R2, R4, #0", "LDR R3, R4, #1", "STR R2, R4, #1", "HALT"],
+    );
+
+    assert_eq!(vm.reg[Reg::R2], 1);
+    assert_eq!(vm.reg[Reg::R3], 0xBEEF);
+    assert_eq!(*written.borrow(), vec![(1, 1)]);
+    // 被盖住的内存既没有被读到也没有被改
+    assert_eq!(vm.memory[0x4000], 0x1111);
+    assert_eq!(vm.memory[0x4001], 0);
+}
+
+#[test]
+fn rejects_empty_or_overlapping_ranges() {
+    let mut vm = Vm::new();
+    vm.map_device(0x4000..=0x4003, counter().0).unwrap();
+
+    #[allow(clippy::reversed_empty_ranges)]
+    let empty = 0x5001..=0x5000;
+    assert_eq!(
+        vm.map_device(empty.clone(), counter().0),
+        Err(MapError::Empty(empty))
+    );
+    assert_eq!(
+        vm.map_device(0x4003..=0x4004, counter().0),
+        Err(MapError::Overlap(0x4003..=0x4004))
+    );
+    // 不能盖住内置的KBSR/KBDR
+    assert_eq!(
+        vm.map_device(0xFDFF..=0xFE00, counter().0),
+        Err(MapError::Overlap(0xFDFF..=0xFE00))
+    );
+    assert_eq!(
+        vm.map_device(0xFE02..=0xFE02, counter().0),
+        Err(MapError::Overlap(0xFE02..=0xFE02))
+    );
+    vm.map_device(0xFE01..=0xFE01, counter().0).unwrap();
+}
+
+#[test]
+fn device_works_past_the_end_of_a_small_memory() {
+    let mut vm = Vm::with_memory_size(0x100);
+    vm.reg[Reg::PC] = 0x10;
+    let (device, written) = counter();
+    vm.map_device(0xFE10..=0xFE11, device).unwrap();
+    load(
+        &mut vm,
+        0x10,
+        &[
+            "LDI R0, #2",
+            "STI R0, #2",
+            "HALT",
+            ".FILL xFE10",
+            ".FILL xFE11",
+        ],
+    );
+    while vm.step_once() == StepResult::Continued {}
+
+    assert_eq!(vm.reg[Reg::R0], 1);
+    assert_eq!(*written.borrow(), vec![(1, 1)]);
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::word;
+use lc_3_vm::asm::{assemble_with_listing, SymbolTable};
+use lc_3_vm::disasm::{disassemble, disassemble_with, listing};
+
+#[test]
+fn operate_instructions() {
+    assert_eq!(
+        disassemble(word("ADD R0, R1, #5"), 0x3000),
+        "ADD R0, R1, #5"
+    );
+    assert_eq!(
+        disassemble(word("ADD R0, R1, R2"), 0x3000),
+        "ADD R0, R1, R2"
+    );
+    assert_eq!(
+        disassemble(word("AND R3, R3, #-1"), 0x3000),
+        "AND R3, R3, #-1"
+    );
+    assert_eq!(disassemble(word("NOT R1, R2"), 0x3000), "NOT R1, R2");
+}
+
+#[test]
+fn pc_relative_targets_use_incremented_pc() {
+    assert_eq!(disassemble(word("BRnzp #15"), 0x3000), "BRnzp 0x3010");
+    assert_eq!(disassemble(word("BRz #-1"), 0x3000), "BRz 0x3000");
+    assert_eq!(disassemble(word("LD R0, #4"), 0x3000), "LD R0, 0x3005");
+    assert_eq!(disassemble(word("LDI R2, #30"), 0x3000), "LDI R2, 0x301f");
+    assert_eq!(disassemble(word("LEA R0, #-2"), 0x3000), "LEA R0, 0x2fff");
+    assert_eq!(disassemble(word("ST R1, #0"), 0x3000), "ST R1, 0x3001");
+    assert_eq!(disassemble(word("STI R1, #1"), 0x3000), "STI R1, 0x3002");
+    assert_eq!(disassemble(word("JSR #-1"), 0x3000), "JSR 0x3000");
+    assert_eq!(disassemble(0x0000, 0x3000), "NOP");
+    assert_eq!(disassemble(0x0005, 0x3000), "NOP");
+}
+
+#[test]
+fn base_register_and_traps() {
+    assert_eq!(
+        disassemble(word("LDR R4, R5, #-2"), 0x3000),
+        "LDR R4, R5, #-2"
+    );
+    assert_eq!(
+        disassemble(word("STR R4, R5, #1"), 0x3000),
+        "STR R4, R5, #1"
+    );
+    assert_eq!(disassemble(word("JMP R2"), 0x3000), "JMP R2");
+    assert_eq!(disassemble(word("JMP R7"), 0x3000), "RET");
+    assert_eq!(disassemble(word("RET"), 0x3000), "RET");
+    assert_eq!(disassemble(word("JSRR R3"), 0x3000), "JSRR R3");
+    assert_eq!(disassemble(word("PUTS"), 0x3000), "PUTS");
+    assert_eq!(disassemble(word("HALT"), 0x3000), "HALT");
+    assert_eq!(disassemble(0xF026, 0x3000), "TRAP 0x26");
+    assert_eq!(disassemble(0x8000, 0x3000), "RTI");
+    assert_eq!(disassemble(0xD000, 0x3000), "RES (illegal)");
+}
+
+#[test]
+fn shows_labels_from_symbol_table() {
+    let (words, symbols, _) =
+        assemble_with_listing(".ORIG x3000\nLOOP ADD R0, R0, #-1\nBRp LOOP\nLEA R1, #5\n.END")
+            .unwrap();
+
+    assert_eq!(
+        disassemble_with(words[2], 0x3001, Some(&symbols)),
+        "BRp LOOP"
+    );
+    // 没有label的地址仍然是十六进制
+    assert_eq!(
+        disassemble_with(words[3], 0x3002, Some(&symbols)),
+        "LEA R1, 0x3008"
+    );
+
+    let mut memory = vec![0u16; 0x3003];
+    memory[0x3000..].copy_from_slice(&words[1..]);
+    assert_eq!(
+        listing(&memory, 0x3000..0x3002, Some(&symbols)),
+        "LOOP:\n0x3000: 0x103f  ADD R0, R0, #-1\n0x3001: 0x03fe  BRp LOOP\n"
+    );
+    assert_eq!(SymbolTable::from_text(&symbols.to_text()), Some(symbols));
+    assert_eq!(SymbolTable::from_text("LOOP"), None);
+}
@@ -0,0 +1,61 @@
+use lc_3_vm::exception::*;
+use lc_3_vm::inline_asm::assemble_line;
+use lc_3_vm::register::Reg;
+use lc_3_vm::{Cond, Privilege, StepResult, Vm, PSR_USER, SSP_START};
+
+/// 用户程序执行到0x3000那条出错的指令，PC已经指向0x3001
+fn machine() -> Vm {
+    let mut vm = Vm::new();
+    vm.reg[Reg::PC] = 0x3001;
+    vm.reg[Reg::R6] = 0x5000;
+    vm.set_cond_codes(Cond::FL_NEG);
+    vm
+}
+
+#[test]
+fn exception_enters_the_routine_from_the_vector_table() {
+    for (vector, routine) in [(PRIVILEGE_VECTOR, 0x1000), (ILLEGAL_OPCODE_VECTOR, 0x1100)] {
+        let mut vm = machine();
+        vm.memory[(EXCEPTION_TABLE + vector) as usize] = routine;
+
+        assert!(vm.raise_exception(vector));
+        assert_eq!(vm.reg[Reg::PC], routine);
+        assert_eq!(vm.reg[Reg::R6], SSP_START - 2);
+        assert_eq!(vm.saved_usp, 0x5000);
+        assert_eq!(vm.psr_privilege(), Privilege::Supervisor);
+        // 栈顶是下一条指令的地址，下面是PSR
+        assert_eq!(vm.memory[(SSP_START - 2) as usize], 0x3001);
+        assert_eq!(
+            vm.memory[(SSP_START - 1) as usize],
+            PSR_USER | Cond::FL_NEG.bits()
+        );
+    }
+}
+
+#[test]
+fn exception_without_a_routine_is_left_to_the_caller() {
+    let mut vm = machine();
+    vm.memory[(EXCEPTION_TABLE + PRIVILEGE_VECTOR) as usize] = 0x1000;
+
+    assert!(!vm.raise_exception(ILLEGAL_OPCODE_VECTOR));
+    assert_eq!(vm.reg[Reg::PC], 0x3001);
+    assert_eq!(vm.reg[Reg::R6], 0x5000);
+    assert_eq!(vm.memory[(SSP_START - 2) as usize], 0);
+}
+
+#[test]
+fn routine_returns_to_the_user_program_with_rti() {
+    let mut vm = Vm::new();
+    vm.trap_exceptions = true;
+    vm.reg[Reg::R6] = 0x5000;
+    vm.memory[(EXCEPTION_TABLE + ILLEGAL_OPCODE_VECTOR) as usize] = 0x1000;
+    vm.memory[0x1000] = assemble_line("RTI").unwrap().unwrap();
+    vm.memory[0x3000] = 0xD000;
+
+    assert_eq!(vm.step_once(), StepResult::Continued);
+    assert_eq!(vm.reg[Reg::PC], 0x1000);
+    assert_eq!(vm.step_once(), StepResult::Continued);
+    assert_eq!(vm.reg[Reg::PC], 0x3001);
+    assert_eq!(vm.reg[Reg::R6], 0x5000);
+    assert_eq!(vm.psr_privilege(), Privilege::User);
+}
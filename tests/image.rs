@@ -0,0 +1,209 @@
+use lc_3_vm::{
+    overlap, read_image_from_bytes, read_image_from_bytes_with, Endian, ImageError, Vm, MEMORY_SIZE,
+};
+
+#[test]
+fn loads_big_endian_words_at_origin() {
+    let mut memory = vec![0u16; MEMORY_SIZE];
+    let data = [0x30, 0x00, 0xF0, 0x25, 0x12, 0x34];
+
+    assert_eq!(
+        read_image_from_bytes(&data, None, &mut memory).unwrap(),
+        0x3000..0x3002
+    );
+    assert_eq!(memory[0x3000], 0xF025);
+    assert_eq!(memory[0x3001], 0x1234);
+}
+
+#[test]
+fn header_only_image_is_empty() {
+    let mut memory = vec![0u16; MEMORY_SIZE];
+    assert_eq!(
+        read_image_from_bytes(&[0x40, 0x00], None, &mut memory).unwrap(),
+        0x4000..0x4000
+    );
+}
+
+#[test]
+fn rejects_malformed_images() {
+    let mut memory = vec![0u16; MEMORY_SIZE];
+    assert!(matches!(
+        read_image_from_bytes(&[0x30], None, &mut memory),
+        Err(ImageError::TruncatedHeader)
+    ));
+    assert!(matches!(
+        read_image_from_bytes(&[0x30, 0x00, 0xF0], None, &mut memory),
+        Err(ImageError::OddLength)
+    ));
+}
+
+#[test]
+fn odd_length_images_are_rejected_without_loading() {
+    let mut memory = vec![0u16; MEMORY_SIZE];
+    // 文件头和一个完整的字后面多出一个字节
+    let err =
+        read_image_from_bytes(&[0x30, 0x00, 0x12, 0x34, 0x56], None, &mut memory).unwrap_err();
+    assert!(matches!(err, ImageError::OddLength));
+    assert_eq!(
+        err.to_string(),
+        "file has a trailing byte that is not part of a word"
+    );
+    // 前面完整的字也不能写进去
+    assert_eq!(memory[0x3000], 0);
+
+    // 没有文件头时只有一个字节也一样
+    assert!(matches!(
+        read_image_from_bytes(&[0x12], Some(0x3000), &mut memory),
+        Err(ImageError::OddLength)
+    ));
+}
+
+#[test]
+fn rejects_images_past_end_of_memory() {
+    let mut memory = vec![0u16; MEMORY_SIZE];
+    let mut data = vec![0xFF, 0xF0];
+    data.extend(std::iter::repeat_n(0xAB, 0x20 * 2));
+
+    assert!(matches!(
+        read_image_from_bytes(&data, None, &mut memory),
+        Err(ImageError::TooLarge {
+            origin: 0xFFF0,
+            words: 0x20
+        })
+    ));
+    // 什么都没有写进去
+    assert_eq!(memory[0xFFF0], 0);
+
+    let mut small = vec![0u16; 0x100];
+    assert!(matches!(
+        read_image_from_bytes(&[0x00, 0xFF, 0x12, 0x34, 0x56, 0x78], None, &mut small),
+        Err(ImageError::TooLarge { .. })
+    ));
+}
+
+#[test]
+fn image_may_end_at_the_last_word_of_memory() {
+    let mut memory = vec![0u16; MEMORY_SIZE];
+    let data = [0xFF, 0xFE, 0x12, 0x34, 0x56, 0x78];
+
+    assert_eq!(
+        read_image_from_bytes(&data, None, &mut memory).unwrap(),
+        0xFFFE..0x10000
+    );
+    assert_eq!(memory[0xFFFF], 0x5678);
+
+    // 没有文件头时也一样，多一个字就放不下了
+    assert_eq!(
+        read_image_from_bytes(&[0xAB, 0xCD], Some(0xFFFF), &mut memory).unwrap(),
+        0xFFFF..0x10000
+    );
+    assert!(matches!(
+        read_image_from_bytes(&[0, 1, 0, 2, 0, 3], Some(0xFFFE), &mut memory),
+        Err(ImageError::TooLarge {
+            origin: 0xFFFE,
+            words: 3
+        })
+    ));
+
+    // 小内存的上限是它自己的长度
+    let mut small = vec![0u16; 0x100];
+    assert_eq!(
+        read_image_from_bytes(&[0x00, 0xFF, 0x12, 0x34], None, &mut small).unwrap(),
+        0xFF..0x100
+    );
+}
+
+#[test]
+fn origin_override_loads_headerless_code() {
+    let mut memory = vec![0u16; MEMORY_SIZE];
+    let data = [0x30, 0x00, 0xF0, 0x25];
+
+    assert_eq!(
+        read_image_from_bytes(&data, Some(0x4000), &mut memory).unwrap(),
+        0x4000..0x4002
+    );
+    // 第一个字也是代码，不再当作起始地址
+    assert_eq!(memory[0x4000], 0x3000);
+    assert_eq!(memory[0x4001], 0xF025);
+    assert_eq!(memory[0x3000], 0);
+
+    assert_eq!(
+        read_image_from_bytes(&[], Some(0x4000), &mut memory).unwrap(),
+        0x4000..0x4000
+    );
+}
+
+#[test]
+fn finds_overlapping_ranges() {
+    assert_eq!(
+        overlap(&(0x3000..0x3010), &(0x300A..0x3020)),
+        Some(0x300A..0x3010)
+    );
+    assert_eq!(
+        overlap(&(0x3000..0x3010), &(0x3004..0x3008)),
+        Some(0x3004..0x3008)
+    );
+    // 首尾相接不算重叠
+    assert_eq!(overlap(&(0x3000..0x3010), &(0x3010..0x3020)), None);
+    assert_eq!(overlap(&(0x3000..0x3000), &(0x2000..0x4000)), None);
+}
+
+#[test]
+fn dumped_memory_reloads_unchanged() {
+    let mut vm = Vm::new();
+    vm.memory[0x3000] = 0xF025;
+    vm.memory[0x3001] = 0x1234;
+
+    let mut data = vec![];
+    vm.dump_memory(0x3000..=0x3001, &mut data).unwrap();
+    assert_eq!(data, [0x30, 0x00, 0xF0, 0x25, 0x12, 0x34]);
+
+    let mut reloaded = Vm::new();
+    assert_eq!(
+        reloaded.read_image_from_bytes(&data, None).unwrap(),
+        0x3000..0x3002
+    );
+    assert_eq!(reloaded.memory, vm.memory);
+}
+
+#[test]
+fn dump_rejects_range_past_end_of_memory() {
+    let vm = Vm::with_memory_size(0x100);
+    let err = vm.dump_memory(0x00F0..=0x0100, &mut vec![]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn little_endian_images_swap_origin_and_words() {
+    let mut memory = vec![0u16; MEMORY_SIZE];
+    let data = [0x00, 0x30, 0x25, 0xF0, 0x34, 0x12];
+    assert_eq!(
+        read_image_from_bytes_with(&data, None, &mut memory, Endian::Little).unwrap(),
+        0x3000..0x3002
+    );
+    assert_eq!(memory[0x3000], 0xF025);
+    assert_eq!(memory[0x3001], 0x1234);
+
+    let mut vm = Vm::new();
+    vm.endian = Endian::Little;
+    assert_eq!(
+        vm.read_image_from_bytes(&data[2..], Some(0x4000)).unwrap(),
+        0x4000..0x4002
+    );
+    assert_eq!(vm.memory[0x4000], 0xF025);
+}
+
+#[test]
+fn loaded_instructions_walk_each_image_in_load_order() {
+    let mut vm = Vm::new();
+    vm.read_image_from_bytes(&[0x40, 0x00, 0x12, 0x34], None)
+        .unwrap();
+    vm.read_image_from_bytes(&[0xF0, 0x25, 0x10, 0x20], Some(0x3000))
+        .unwrap();
+    vm.memory[0x3002] = 0xFFFF;
+
+    assert_eq!(
+        vm.loaded_instructions().collect::<Vec<_>>(),
+        vec![(0x4000, 0x1234), (0x3000, 0xF025), (0x3001, 0x1020)]
+    );
+}
@@ -0,0 +1,92 @@
+use lc_3_vm::inline_asm::*;
+
+#[test]
+fn strips_comments() {
+    assert_eq!(strip_comment("ADD R0, R0, #1 ; bump"), "ADD R0, R0, #1 ");
+    assert_eq!(strip_comment("; only a comment"), "");
+    assert_eq!(tokenize("  NOT R1,R2;x"), vec!["NOT", "R1", "R2"]);
+    assert_eq!(assemble_line("   ; nothing here"), Ok(None));
+    assert_eq!(assemble_line(""), Ok(None));
+}
+
+#[test]
+fn parses_operand_forms() {
+    assert_eq!(parse_operand("R7"), Ok(Operand::Reg(7)));
+    assert_eq!(parse_operand("r3"), Ok(Operand::Reg(3)));
+    assert_eq!(parse_operand("#-5"), Ok(Operand::Imm(-5)));
+    assert_eq!(parse_operand("#12"), Ok(Operand::Imm(12)));
+    assert_eq!(parse_operand("x3000"), Ok(Operand::Imm(0x3000)));
+    assert_eq!(parse_operand("0x1F"), Ok(Operand::Imm(0x1F)));
+    assert_eq!(parse_operand("b101"), Ok(Operand::Imm(5)));
+    assert_eq!(parse_operand("42"), Ok(Operand::Imm(42)));
+    assert_eq!(
+        parse_operand("LOOP"),
+        Ok(Operand::Label("LOOP".to_string()))
+    );
+    assert_eq!(
+        parse_operand("R8!"),
+        Err(LineError::BadOperand("R8!".to_string()))
+    );
+}
+
+#[test]
+fn encodes_register_and_immediate_forms() {
+    assert_eq!(assemble_line("ADD R0, R1, R2"), Ok(Some(0x1042)));
+    assert_eq!(assemble_line("ADD R0, R1, #5"), Ok(Some(0x1065)));
+    assert_eq!(assemble_line("add r0, r1, #-1"), Ok(Some(0x107F)));
+    assert_eq!(assemble_line("AND R2, R2, #0"), Ok(Some(0x54A0)));
+    assert_eq!(assemble_line("NOT R1, R2"), Ok(Some(0x92BF)));
+}
+
+#[test]
+fn encodes_offsets_and_base_registers() {
+    assert_eq!(assemble_line("LD R0, #2"), Ok(Some(0x2002)));
+    assert_eq!(assemble_line("LDI R3, #-1"), Ok(Some(0xA7FF)));
+    assert_eq!(assemble_line("LEA R0, x10"), Ok(Some(0xE010)));
+    assert_eq!(assemble_line("ST R1, #0"), Ok(Some(0x3200)));
+    assert_eq!(assemble_line("STI R1, #3"), Ok(Some(0xB203)));
+    assert_eq!(assemble_line("LDR R4, R5, #-2"), Ok(Some(0x697E)));
+    assert_eq!(assemble_line("STR R4, R5, #1"), Ok(Some(0x7941)));
+    assert_eq!(assemble_line("JMP R2"), Ok(Some(0xC080)));
+    assert_eq!(assemble_line("RET"), Ok(Some(0xC1C0)));
+    assert_eq!(assemble_line("RET"), assemble_line("JMP R7"));
+    assert_eq!(assemble_line("JSRR R3"), Ok(Some(0x40C0)));
+    assert_eq!(assemble_line("JSR #-1"), Ok(Some(0x4FFF)));
+    assert_eq!(assemble_line("BRz #-3"), Ok(Some(0x05FD)));
+    assert_eq!(assemble_line("BR #1"), Ok(Some(0x0E01)));
+    assert_eq!(assemble_line("BRnp #0"), Ok(Some(0x0A00)));
+    assert_eq!(assemble_line("NOP"), Ok(Some(0x0000)));
+}
+
+#[test]
+fn encodes_traps_and_fill() {
+    assert_eq!(assemble_line("HALT"), Ok(Some(0xF025)));
+    assert_eq!(assemble_line("PUTS ; print"), Ok(Some(0xF022)));
+    assert_eq!(assemble_line("TRAP x21"), Ok(Some(0xF021)));
+    assert_eq!(assemble_line(".FILL x1234"), Ok(Some(0x1234)));
+    assert_eq!(assemble_line(".fill #-1"), Ok(Some(0xFFFF)));
+}
+
+#[test]
+fn reports_errors() {
+    assert_eq!(
+        assemble_line("ADD R0, R1, #16"),
+        Err(LineError::OutOfRange { value: 16, bits: 5 })
+    );
+    assert_eq!(
+        assemble_line("FOO R0"),
+        Err(LineError::UnknownMnemonic("FOO".to_string()))
+    );
+    assert_eq!(
+        assemble_line("NOT R0"),
+        Err(LineError::OperandCount {
+            mnemonic: "NOT".to_string(),
+            expected: 2,
+            found: 1,
+        })
+    );
+    assert_eq!(
+        assemble_line("BRz LOOP"),
+        Err(LineError::UnresolvedLabel("LOOP".to_string()))
+    );
+}
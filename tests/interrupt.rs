@@ -0,0 +1,63 @@
+mod common;
+
+use common::load;
+use lc_3_vm::interrupt::KBSR_IE;
+use lc_3_vm::register::Reg;
+use lc_3_vm::{Cond, MemMapReg, Privilege, Vm, PSR_USER, SSP_START};
+use std::io::Cursor;
+
+/// 用户程序在0x3000原地循环，键盘中断服务例程在0x1000读走键再RTI
+fn interrupt_vm(input: &[u8]) -> Vm {
+    let mut vm = Vm::new();
+    vm.set_input(Cursor::new(input.to_vec()));
+    vm.memory[0x0180] = 0x1000;
+    load(&mut vm, 0x1000, &["LDI R0, #1", "RTI", ".FILL xFE02"]);
+    load(&mut vm, 0x3000, &["BRnzp #-1"]);
+    vm.reg[Reg::R6] = 0x5000;
+    vm.set_cond_codes(Cond::FL_ZRO);
+    vm
+}
+
+#[test]
+fn keypress_vectors_through_table_and_returns() {
+    let mut vm = interrupt_vm(b"k");
+    vm.mem_write(MemMapReg::MR_KBSR as u16, KBSR_IE);
+
+    // 进入中断之后这一步执行的就是例程的第一条指令
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::R0], b'k' as u16);
+    assert_eq!(vm.psr_privilege(), Privilege::Supervisor);
+    assert_eq!(vm.priority(), 4);
+    assert_eq!(vm.reg[Reg::R6], SSP_START - 2);
+    assert_eq!(vm.memory[(SSP_START - 2) as usize], 0x3000);
+    assert_eq!(vm.saved_usp, 0x5000);
+
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::PC], 0x3000);
+    assert_eq!(vm.reg[Reg::R6], 0x5000);
+    assert_ne!(vm.reg[Reg::PSR] & PSR_USER, 0);
+
+    // 键已经被取走，IE还在
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::PC], 0x3000);
+    assert_eq!(vm.memory[MemMapReg::MR_KBSR as usize], KBSR_IE);
+}
+
+#[test]
+fn no_interrupt_without_enable_bit() {
+    let mut vm = interrupt_vm(b"k");
+
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::PC], 0x3000);
+    assert_eq!(vm.psr_privilege(), Privilege::User);
+    // 键还在输入里，没有被读走
+    assert_eq!(vm.read_byte(), Some(b'k'));
+}
+
+#[test]
+fn kbsr_ready_bit_is_read_only() {
+    let mut vm = interrupt_vm(b"");
+    vm.mem_write(MemMapReg::MR_KBSR as u16, 0xFFFF);
+    assert_eq!(vm.memory[MemMapReg::MR_KBSR as usize], KBSR_IE);
+    assert_eq!(vm.mem_read(MemMapReg::MR_KBSR as u16), KBSR_IE);
+}
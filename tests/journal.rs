@@ -0,0 +1,109 @@
+mod common;
+
+use common::load;
+use lc_3_vm::journal::Journal;
+use lc_3_vm::register::Reg;
+use lc_3_vm::Vm;
+
+#[test]
+fn step_back_reverts_registers_and_memory() {
+    let mut vm = Vm::new();
+    vm.journal = Some(Journal::new(16));
+    load(
+        &mut vm,
+        0x3000,
+        &["ADD R1, R1, #5", "ST R1, #2", "ADD R1, R1, #1", "HALT"],
+    );
+    vm.memory[0x3004] = 0x1234;
+
+    let start = vm.reg;
+    vm.step_once();
+    let after_add = vm.reg;
+    vm.step_once();
+    vm.step_once();
+    assert_eq!(vm.memory[0x3004], 5);
+    assert_eq!(vm.reg[Reg::R1], 6);
+
+    assert!(vm.step_back());
+    assert_eq!(vm.reg[Reg::R1], 5);
+    assert_eq!(vm.reg[Reg::PC], 0x3002);
+
+    assert!(vm.step_back());
+    assert_eq!(vm.memory[0x3004], 0x1234);
+    assert_eq!(vm.reg, after_add);
+
+    assert!(vm.step_back());
+    assert_eq!(vm.reg, start);
+    assert!(!vm.step_back());
+}
+
+#[test]
+fn journal_keeps_only_the_last_depth_steps() {
+    let mut vm = Vm::new();
+    vm.journal = Some(Journal::new(2));
+    load(&mut vm, 0x3000, &["ADD R1, R1, #1"; 5]);
+
+    for _ in 0..5 {
+        vm.step_once();
+    }
+    assert_eq!(vm.journal.as_ref().unwrap().len(), 2);
+    assert!(vm.step_back());
+    assert!(vm.step_back());
+    assert!(!vm.step_back());
+    assert_eq!(vm.reg[Reg::R1], 3);
+}
+
+#[test]
+fn halt_can_be_undone() {
+    let mut vm = Vm::new();
+    vm.journal = Some(Journal::new(4));
+    load(&mut vm, 0x3000, &["HALT"]);
+    vm.run_until_halt(&b""[..], std::io::sink()).unwrap();
+
+    assert!(!vm.running);
+    assert!(vm.step_back());
+    assert!(vm.running);
+    assert_eq!(vm.reg[Reg::PC], 0x3000);
+}
+
+#[test]
+fn rewind_returns_to_the_last_breakpoint_hit() {
+    let mut vm = Vm::new();
+    vm.journal = Some(Journal::new(16));
+    load(&mut vm, 0x3000, &["ADD R1, R1, #1"; 8]);
+
+    assert_eq!(vm.rewind_to_breakpoint(), None);
+
+    // 调试器在执行第2条和第4条之前命中断点
+    vm.step_once();
+    vm.journal.as_mut().unwrap().mark_breakpoint();
+    vm.step_once();
+    vm.step_once();
+    vm.journal.as_mut().unwrap().mark_breakpoint();
+    let at_second_hit = vm.reg;
+    for _ in 0..3 {
+        vm.step_once();
+    }
+    assert_eq!(vm.reg[Reg::R1], 6);
+
+    assert_eq!(vm.rewind_to_breakpoint(), Some(3));
+    assert_eq!(vm.reg, at_second_hit);
+    assert_eq!(vm.rewind_to_breakpoint(), Some(2));
+    assert_eq!(vm.reg[Reg::PC], 0x3001);
+    assert_eq!(vm.rewind_to_breakpoint(), None);
+    assert_eq!(vm.reg[Reg::PC], 0x3001);
+}
+
+#[test]
+fn rewind_fails_when_the_hit_fell_out_of_the_history() {
+    let mut vm = Vm::new();
+    vm.journal = Some(Journal::new(2));
+    load(&mut vm, 0x3000, &["ADD R1, R1, #1"; 4]);
+
+    vm.journal.as_mut().unwrap().mark_breakpoint();
+    for _ in 0..4 {
+        vm.step_once();
+    }
+    assert_eq!(vm.rewind_to_breakpoint(), None);
+    assert_eq!(vm.reg[Reg::R1], 4);
+}
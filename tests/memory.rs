@@ -0,0 +1,273 @@
+mod common;
+
+use common::load;
+use lc_3_vm::register::Reg;
+use lc_3_vm::trapcode::{puts_bytes, putsp_bytes, MAX_STRING_WORDS};
+use lc_3_vm::{MemMapReg, MemoryFault, StepResult, Vm, WatchHit};
+
+#[test]
+fn unchecked_by_default() {
+    let mut vm = Vm::new();
+    vm.mem_write(0x8000, 7);
+    assert_eq!(vm.mem_read(0x8000), 7);
+    assert_eq!(vm.fault, None);
+}
+
+#[test]
+fn reports_access_past_memory_limit() {
+    let mut vm = Vm::new();
+    vm.memory_limit = Some(0x4000);
+    vm.reg[Reg::R1] = 0x4000;
+    load(
+        &mut vm,
+        0x3000,
+        &["LDR R0, R1, #0", "STR R0, R1, #-1", "STR R0, R1, #0"],
+    );
+
+    assert_eq!(
+        vm.step_once(),
+        StepResult::IllegalAccess {
+            pc: 0x3000,
+            fault: MemoryFault {
+                addr: 0x4000,
+                write: false
+            }
+        }
+    );
+
+    vm.reg[Reg::PC] = 0x3001;
+    assert_eq!(vm.step_once(), StepResult::Continued);
+
+    vm.memory[0x4000] = 0x1234;
+    vm.reg[Reg::R0] = 5;
+    assert_eq!(
+        vm.step_once(),
+        StepResult::IllegalAccess {
+            pc: 0x3002,
+            fault: MemoryFault {
+                addr: 0x4000,
+                write: true
+            }
+        }
+    );
+    // 非法写入被丢掉了
+    assert_eq!(vm.memory[0x4000], 0x1234);
+}
+
+#[test]
+fn device_page_only_allows_real_devices() {
+    let mut vm = Vm::new();
+    vm.memory_limit = Some(0x10000);
+    vm.reg[Reg::R1] = 0xFE02;
+    load(&mut vm, 0x3000, &["STR R0, R1, #0", "STR R0, R1, #1"]);
+
+    assert_eq!(vm.step_once(), StepResult::Continued);
+    assert_eq!(
+        vm.step_once(),
+        StepResult::IllegalAccess {
+            pc: 0x3001,
+            fault: MemoryFault {
+                addr: 0xFE03,
+                write: true
+            }
+        }
+    );
+}
+
+#[test]
+fn puts_stops_at_missing_terminator() {
+    let mut memory = vec![b'x' as u16; 0x10000];
+
+    let (bytes, terminated) = puts_bytes(&memory, 0x3000);
+    assert!(!terminated);
+    assert_eq!(bytes.len(), MAX_STRING_WORDS);
+
+    memory[0x3000 + MAX_STRING_WORDS - 1] = 0;
+    assert!(puts_bytes(&memory, 0x3000).1);
+
+    // 起点已经在设备区里，一个字也不输出
+    assert_eq!(puts_bytes(&memory, 0xFE00), (vec![], false));
+}
+
+#[test]
+fn display_is_always_ready() {
+    let mut vm = Vm::new();
+    assert_eq!(vm.mem_read(MemMapReg::MR_DSR as u16), 0x8000);
+
+    vm.mem_write(MemMapReg::MR_DDR as u16, b'\n' as u16);
+    assert_eq!(vm.memory[MemMapReg::MR_DDR as usize], b'\n' as u16);
+}
+
+#[test]
+fn putsp_unpacks_low_byte_first() {
+    let mut memory = vec![0u16; 0x4000];
+    memory[0x3000] = u16::from_be_bytes([b'i', b'H']);
+    memory[0x3001] = b'!' as u16;
+
+    assert_eq!(putsp_bytes(&memory, 0x3000), (b"Hi!".to_vec(), true));
+}
+
+#[test]
+fn putsp_stops_at_the_first_zero_byte() {
+    let mut memory = vec![0u16; 0x4000];
+    // 奇数长度："abc"，最后一个字高8位是0
+    memory[0x3000] = u16::from_be_bytes([b'b', b'a']);
+    memory[0x3001] = u16::from_be_bytes([0, b'c']);
+    memory[0x3002] = u16::from_be_bytes([b'x', b'y']);
+    assert_eq!(putsp_bytes(&memory, 0x3000), (b"abc".to_vec(), true));
+
+    // 低8位是0的字在它前面就结束了，后面的'A'不会输出
+    memory[0x3100] = u16::from_be_bytes([b'k', b'o']);
+    memory[0x3101] = u16::from_be_bytes([b'A', 0]);
+    memory[0x3102] = u16::from_be_bytes([b'y', b'x']);
+    assert_eq!(putsp_bytes(&memory, 0x3100), (b"ok".to_vec(), true));
+
+    // 偶数长度的字符串后面跟着全0的字
+    memory[0x3200] = u16::from_be_bytes([b'k', b'o']);
+    assert_eq!(putsp_bytes(&memory, 0x3200), (b"ok".to_vec(), true));
+}
+
+#[test]
+fn clock_counts_milliseconds_since_start() {
+    let mut vm = Vm::new();
+    vm.started -= std::time::Duration::from_millis(1500);
+
+    let ms = vm.mem_read(MemMapReg::MR_CLK as u16);
+    assert!((1500..2500).contains(&ms));
+    // 读时钟不会改内存
+    assert_eq!(vm.memory[MemMapReg::MR_CLK as usize], 0);
+}
+
+#[test]
+fn watchpoint_records_store() {
+    let mut vm = Vm::new();
+    vm.watchpoints.insert(0x3004);
+    vm.memory[0x3004] = 7;
+    vm.reg[Reg::R1] = 9;
+    load(
+        &mut vm,
+        0x3000,
+        &["ST R1, #3", "ST R1, #1", "ADD R0, R0, #1"],
+    );
+
+    vm.step_once();
+    assert_eq!(
+        vm.watch_hit,
+        Some(WatchHit {
+            addr: 0x3004,
+            old: 7,
+            new: 9,
+            pc: 0x3000,
+        })
+    );
+
+    // 写别的地址不算，上一次的记录在下一条指令开始时清掉
+    vm.step_once();
+    assert_eq!(vm.watch_hit, None);
+}
+
+#[test]
+fn small_memory_has_no_devices() {
+    let mut vm = Vm::with_memory_size(0x100);
+    assert_eq!(vm.memory.len(), 0x100);
+    vm.reg[Reg::PC] = 0x10;
+    vm.reg[Reg::R0] = b'k' as u16;
+    load(&mut vm, 0x10, &["OUT", "LDI R1, #0", ".FILL xFE00"]);
+
+    // trap不经过DDR，照样能输出
+    vm.sink = None;
+    assert_eq!(vm.step_once(), StepResult::Continued);
+    assert_eq!(vm.output, b"k");
+
+    assert_eq!(
+        vm.step_once(),
+        StepResult::IllegalAccess {
+            pc: 0x11,
+            fault: MemoryFault {
+                addr: 0xFE00,
+                write: false
+            }
+        }
+    );
+
+    // 跑出内存末尾也一样
+    vm.reg[Reg::PC] = 0x100;
+    assert_eq!(
+        vm.step_once(),
+        StepResult::IllegalAccess {
+            pc: 0x100,
+            fault: MemoryFault {
+                addr: 0x100,
+                write: false
+            }
+        }
+    );
+}
+
+#[test]
+fn code_writes_are_reported_but_still_happen() {
+    let mut vm = Vm::new();
+    vm.warn_code_writes = true;
+    // ST R0, #-1：把R0写到自己这条指令上
+    let range = vm
+        .read_image_from_bytes(&[0x30, 0x00, 0x31, 0xFF, 0xF0, 0x25], None)
+        .unwrap();
+    assert_eq!(vm.code_ranges, [range]);
+    assert!(vm.in_code(0x3001));
+    assert!(!vm.in_code(0x3002));
+
+    vm.reg[Reg::R0] = 0xF025;
+    vm.step_once();
+    assert_eq!(vm.memory[0x3000], 0xF025);
+}
+
+#[test]
+fn peek_does_not_poll_or_consume_keys() {
+    let mut vm = Vm::new();
+    vm.set_input(std::io::Cursor::new(b"k".to_vec()));
+    vm.memory[0x3000] = 0x1234;
+
+    assert_eq!(vm.peek(0x3000), 0x1234);
+    assert_eq!(vm.peek(MemMapReg::MR_KBSR as u16), 0);
+    assert_eq!(vm.peek(MemMapReg::MR_KBDR as u16), 0);
+
+    // 键还在，真正执行时的读仍然能拿到
+    assert_eq!(vm.mem_read(MemMapReg::MR_KBSR as u16) & 0x8000, 0x8000);
+    assert_eq!(vm.mem_read(MemMapReg::MR_KBDR as u16), b'k' as u16);
+    assert_eq!(vm.peek(MemMapReg::MR_KBDR as u16), b'k' as u16);
+}
+
+#[test]
+fn peek_past_end_of_memory_reads_zero() {
+    let vm = Vm::with_memory_size(0x4000);
+    assert_eq!(vm.peek(0x5000), 0);
+}
+
+#[test]
+fn keyboard_poll_with_memory_cut_inside_the_device_page() {
+    for size in [0xFE01, 0xFE02] {
+        let mut vm = Vm::with_memory_size(size);
+        vm.set_input(std::io::Cursor::new(b"k".to_vec()));
+
+        // KBSR还在内存里，KBDR已经不在了：轮询照样报告有键，只是没地方放键值
+        assert_eq!(vm.mem_read(MemMapReg::MR_KBSR as u16), 0x8000);
+        assert_eq!(vm.mem_read(MemMapReg::MR_KBDR as u16), 0);
+        assert!(vm.fault.is_some());
+    }
+}
+
+#[test]
+fn device_registers_past_a_small_memory_with_memory_limit() {
+    let mut vm = Vm::with_memory_size(0x100);
+    vm.memory_limit = Some(0x100);
+    vm.set_input(std::io::Cursor::new(b"k".to_vec()));
+
+    // 设备寄存器照样能访问，只是值不在memory里
+    assert_eq!(vm.mem_read(MemMapReg::MR_DSR as u16), 0x8000);
+    assert_eq!(vm.mem_read(MemMapReg::MR_KBSR as u16), 0x8000);
+    assert_eq!(vm.mem_read(MemMapReg::MR_KBDR as u16), b'k' as u16);
+    vm.mem_write(MemMapReg::MR_MCR as u16, 0);
+    assert!(!vm.running);
+    assert_eq!(vm.fault, None);
+    assert_eq!(vm.memory.len(), 0x100);
+}
@@ -0,0 +1,290 @@
+mod common;
+
+use common::word;
+use lc_3_vm::opcode::*;
+use lc_3_vm::opcodes::{decode_opcode, OpCodes};
+use lc_3_vm::register::Reg;
+use lc_3_vm::{format_word, new_registers, to_signed, update_flags, Cond, StepResult, Vm, VmError};
+
+const P: u16 = Cond::FL_POS.bits();
+const Z: u16 = Cond::FL_ZRO.bits();
+const N: u16 = Cond::FL_NEG.bits();
+
+#[test]
+fn add_immediate_and_register() {
+    let mut reg = new_registers();
+    reg[Reg::R1] = 10;
+    reg[Reg::R2] = 7;
+
+    op_add(&mut reg, word("ADD R0, R1, #5"));
+    assert_eq!(reg[Reg::R0], 15);
+    assert_eq!(reg[Reg::COND], P);
+
+    op_add(&mut reg, word("ADD R3, R1, R2"));
+    assert_eq!(reg[Reg::R3], 17);
+}
+
+#[test]
+fn add_sign_extends_imm5() {
+    let mut reg = new_registers();
+    reg[Reg::R1] = 1;
+
+    // imm5 = 0x1F 是 -1
+    op_add(&mut reg, 0x1000 | 1 << 6 | 1 << 5 | 0x1F);
+    assert_eq!(reg[Reg::R0], 0);
+    assert_eq!(reg[Reg::COND], Z);
+
+    op_add(&mut reg, word("ADD R0, R0, #-1"));
+    assert_eq!(reg[Reg::R0], 0xFFFF);
+    assert_eq!(reg[Reg::COND], N);
+}
+
+#[test]
+fn add_wraps_around() {
+    let mut reg = new_registers();
+    reg[Reg::R1] = 0xFFFF;
+    reg[Reg::R2] = 2;
+
+    op_add(&mut reg, word("ADD R0, R1, R2"));
+    assert_eq!(reg[Reg::R0], 1);
+    assert_eq!(reg[Reg::COND], P);
+}
+
+#[test]
+fn detects_signed_add_overflow() {
+    let mut reg = new_registers();
+    reg[Reg::R1] = 0x7FFF;
+    reg[Reg::R2] = 0xFFFF;
+
+    assert_eq!(
+        add_overflows(&reg, word("ADD R0, R1, #1")),
+        Some((0x7FFF, 1))
+    );
+    // -1 + 0x7FFF不溢出，虽然按无符号数回绕了
+    assert_eq!(add_overflows(&reg, word("ADD R0, R1, R2")), None);
+
+    reg[Reg::R3] = 0x8000;
+    assert_eq!(
+        add_overflows(&reg, word("ADD R0, R3, R2")),
+        Some((0x8000, 0xFFFF))
+    );
+}
+
+#[test]
+fn and_and_not() {
+    let mut reg = new_registers();
+    reg[Reg::R1] = 0b1100;
+    reg[Reg::R2] = 0b1010;
+
+    op_and(&mut reg, word("AND R0, R1, R2"));
+    assert_eq!(reg[Reg::R0], 0b1000);
+
+    op_and(&mut reg, word("AND R0, R0, #0"));
+    assert_eq!(reg[Reg::R0], 0);
+    assert_eq!(reg[Reg::COND], Z);
+
+    op_not(&mut reg, word("NOT R3, R0"));
+    assert_eq!(reg[Reg::R3], 0xFFFF);
+    assert_eq!(reg[Reg::COND], N);
+}
+
+#[test]
+fn branch_follows_condition_flags() {
+    let mut reg = new_registers();
+    reg[Reg::PC] = 0x3001;
+    reg[Reg::COND] = Z;
+
+    op_branch(&mut reg, word("BRp #5"));
+    assert_eq!(reg[Reg::PC], 0x3001);
+
+    op_branch(&mut reg, word("BRz #-1"));
+    assert_eq!(reg[Reg::PC], 0x3000);
+}
+
+#[test]
+fn branch_without_condition_bits_is_a_nop() {
+    for cond in [N, Z, P] {
+        let mut vm = Vm::new();
+        vm.reg[Reg::COND] = cond;
+        // nzp = 000，偏移量不管是多少都不跳
+        vm.memory[0x3000] = 0x0005;
+        let before = vm.reg;
+
+        vm.step_once();
+        assert_eq!(vm.reg[Reg::PC], 0x3001);
+        let mut expected = before;
+        expected[Reg::PC] = 0x3001;
+        assert_eq!(vm.reg, expected);
+    }
+}
+
+#[test]
+fn jumps_and_subroutines() {
+    let mut reg = new_registers();
+    reg[Reg::PC] = 0x3001;
+    reg[Reg::R2] = 0x4000;
+
+    op_jsr(&mut reg, word("JSR #15"));
+    assert_eq!(reg[Reg::R7], 0x3001);
+    assert_eq!(reg[Reg::PC], 0x3010);
+
+    op_jsr(&mut reg, word("JSRR R2"));
+    assert_eq!(reg[Reg::R7], 0x3010);
+    assert_eq!(reg[Reg::PC], 0x4000);
+
+    op_jump(&mut reg, word("RET"));
+    assert_eq!(reg[Reg::PC], 0x3010);
+}
+
+#[test]
+fn lea_computes_pc_relative_address() {
+    let mut reg = new_registers();
+    reg[Reg::PC] = 0x3001;
+
+    op_lea(&mut reg, word("LEA R0, #-2"));
+    assert_eq!(reg[Reg::R0], 0x2FFF);
+    assert_eq!(reg[Reg::COND], P);
+}
+
+#[test]
+fn loads_and_stores_go_through_memory() {
+    let mut vm = Vm::new();
+    vm.reg[Reg::PC] = 0x3001;
+    vm.memory[0x3005] = 0x4000;
+    vm.memory[0x4000] = 0x8001;
+
+    vm.op_load(word("LD R0, #4"));
+    assert_eq!(vm.reg[Reg::R0], 0x4000);
+
+    vm.op_ldi(word("LDI R1, #4"));
+    assert_eq!(vm.reg[Reg::R1], 0x8001);
+    assert_eq!(vm.reg[Reg::COND], N);
+
+    vm.op_ldr(word("LDR R2, R0, #0"));
+    assert_eq!(vm.reg[Reg::R2], 0x8001);
+
+    vm.reg[Reg::R3] = 42;
+    vm.op_st(word("ST R3, #0"));
+    assert_eq!(vm.memory[0x3001], 42);
+
+    vm.op_sti(word("STI R3, #4"));
+    assert_eq!(vm.memory[0x4000], 42);
+
+    vm.op_str(word("STR R3, R0, #-1"));
+    assert_eq!(vm.memory[0x3FFF], 42);
+}
+
+#[test]
+fn cond_bit_tests() {
+    let nzp = Cond::from_bits(0b111);
+    assert!(nzp.contains(Cond::FL_NEG));
+    assert!(!Cond::FL_ZRO.contains(Cond::FL_NEG));
+    assert!(Cond::from_bits(0b011).intersects(Cond::FL_POS));
+    assert!(!Cond::from_bits(0b000).intersects(Cond::FL_ZRO));
+
+    assert_eq!(Cond::of(0), Cond::FL_ZRO);
+    assert_eq!(Cond::of(0x7FFF), Cond::FL_POS);
+    assert_eq!(Cond::of(0x8000), Cond::FL_NEG);
+
+    let mut reg = new_registers();
+    reg[Reg::R2] = 0xFFFF;
+    assert_eq!(update_flags(Reg::R2 as usize, &mut reg), Cond::FL_NEG);
+    assert_eq!(reg[Reg::COND], N);
+}
+
+#[test]
+fn tracks_last_r7_writer_for_clobber_warning() {
+    let mut vm = Vm::new();
+    vm.warn_r7_clobber = true;
+    for (i, line) in ["LEA R7, #5", "JSR #0", "ADD R0, R0, #0"]
+        .iter()
+        .enumerate()
+    {
+        vm.memory[0x3000 + i] = word(line);
+    }
+
+    vm.step_once();
+    assert_eq!(vm.r7_writer, Some(0x3000));
+    // JSR照常覆盖R7，只是把记录清掉
+    vm.step_once();
+    assert_eq!(vm.r7_writer, None);
+    assert_eq!(vm.reg[Reg::R7], 0x3002);
+}
+
+#[test]
+fn signed_display_at_boundaries() {
+    assert_eq!(to_signed(0x7FFF), 32767);
+    assert_eq!(to_signed(0x8000), -32768);
+    assert_eq!(to_signed(0xFFFF), -1);
+    assert_eq!(format_word(0x7FFF), "0x7fff (32767)");
+    assert_eq!(format_word(0x8000), "0x8000 (-32768)");
+    assert_eq!(format_word(0xFFFF), "0xffff (-1)");
+}
+
+#[test]
+fn check_cond_accepts_flags_set_by_instructions() {
+    let mut vm = Vm::new();
+    vm.check_cond = true;
+    // 开机时COND还是0，不算错
+    vm.memory[0x3000] = word("BRnzp #0");
+    vm.memory[0x3001] = word("ADD R0, R0, #-1");
+    vm.memory[0x3002] = word("AND R0, R0, #0");
+    for _ in 0..3 {
+        vm.step_once();
+    }
+    assert_eq!(vm.reg[Reg::COND], Z);
+}
+
+#[test]
+fn check_cond_catches_multiple_flags() {
+    let mut vm = Vm::new();
+    vm.check_cond = true;
+    vm.reg[Reg::COND] = N | P;
+    vm.memory[0x3000] = word("BRz #0");
+    assert_eq!(
+        vm.step_once(),
+        StepResult::BadCond {
+            pc: 0x3000,
+            instr: word("BRz #0"),
+            cond: N | P
+        }
+    );
+
+    // step把它报告成错误，不会panic
+    vm.reg[Reg::PC] = 0x3000;
+    let error = vm.step().unwrap_err();
+    assert!(matches!(error, VmError::BadCond { pc: 0x3000, .. }));
+    assert_eq!(
+        error.to_string(),
+        "COND is 0b101 after 0x0400 at 0x3000, expected exactly one of N/Z/P"
+    );
+}
+
+#[test]
+fn wild_jumps_still_execute() {
+    let mut vm = Vm::new();
+    vm.warn_wild_jumps = true;
+    // JMP R1，R1指向镜像以外
+    vm.read_image_from_bytes(&[0x30, 0x00, 0xC0, 0x40], None)
+        .unwrap();
+    vm.reg[Reg::R1] = 0x8123;
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::PC], 0x8123);
+    assert!(!vm.in_code(0x8123));
+}
+
+#[test]
+fn decode_opcode_covers_every_encoding() {
+    assert_eq!(decode_opcode(word("ADD R0, R1, #5")), OpCodes::OP_ADD);
+    assert_eq!(decode_opcode(word("RET")), OpCodes::OP_JMP);
+    assert_eq!(decode_opcode(word("HALT")), OpCodes::OP_TRAP);
+    assert_eq!(decode_opcode(0x0000), OpCodes::OP_BR);
+    assert_eq!(decode_opcode(0x8000), OpCodes::OP_RTI);
+    assert_eq!(decode_opcode(0xD000), OpCodes::OP_RES);
+
+    for opcode in 0..16u16 {
+        assert_eq!(OpCodes::try_from(opcode).map(|op| op as u16), Ok(opcode));
+        assert_eq!(decode_opcode(opcode << 12 | 0x0ABC) as u16, opcode);
+    }
+    assert_eq!(OpCodes::try_from(16), Err(16));
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::load;
+use lc_3_vm::cycles::Cycles;
+use lc_3_vm::profile::Profile;
+use lc_3_vm::{StepResult, Vm};
+
+#[test]
+fn counts_opcodes_and_traps() {
+    let mut vm = Vm::new();
+    vm.profile = Some(Profile::default());
+    let program = ["AND R0, R0, #0", "ADD R0, R0, #1", "ADD R0, R0, #1", "HALT"];
+    load(&mut vm, 0x3000, &program);
+
+    while vm.step_once() == StepResult::Continued {}
+
+    let profile = vm.profile.unwrap();
+    assert_eq!(profile.opcodes[0b0001], 2);
+    assert_eq!(profile.opcodes[0b0101], 1);
+    assert_eq!(profile.opcodes[0b1111], 1);
+    assert_eq!(profile.traps[0x25], 1);
+    assert_eq!(
+        profile.report(),
+        "Opcode histogram (4 instructions):\n\
+         \x20 ADD                 2  50.00%\n\
+         \x20 AND                 1  25.00%\n\
+         \x20 TRAP                1  25.00%\n\
+         Trap histogram (1 traps):\n\
+         \x20 HALT                1 100.00%\n"
+    );
+}
+
+#[test]
+fn accumulates_cycles_from_the_model() {
+    let mut vm = Vm::new();
+    vm.cycles = Some(Cycles::default());
+    let program = [
+        "ADD R0, R0, #1",
+        "LD R1, #2",
+        "LDI R2, #2",
+        "HALT",
+        ".FILL x4000",
+        ".FILL xFE04",
+    ];
+    load(&mut vm, 0x3000, &program);
+
+    while vm.step_once() == StepResult::Continued {}
+
+    // ADD 1 + LD 2 + LDI 3 + 读DSR的设备开销4 + TRAP 2
+    assert_eq!(vm.cycles.unwrap().total, 12);
+}
@@ -0,0 +1,257 @@
+mod common;
+
+use common::load;
+use lc_3_vm::asm::{assemble, to_bytes};
+use lc_3_vm::register::Reg;
+use lc_3_vm::{EofPolicy, StepResult, Vm, VmError, EOF_SENTINEL};
+
+#[test]
+fn echoes_input_into_captured_output() {
+    let mut vm = Vm::new();
+    load(
+        &mut vm,
+        0x3000,
+        &["GETC", "OUT", "GETC", "OUT", "LEA R0, #2", "PUTS", "HALT"],
+    );
+    for (i, c) in "!\n".bytes().enumerate() {
+        vm.memory[0x3007 + i] = c as u16;
+    }
+
+    let mut output = vec![];
+    vm.run_until_halt(&b"ok"[..], &mut output).unwrap();
+    assert_eq!(output, b"ok!\n");
+    assert!(!vm.running);
+}
+
+#[test]
+fn polls_keyboard_registers_from_input() {
+    let mut vm = Vm::new();
+    // 等KBSR就绪后读KBDR，两次读到的都是输入里的字节
+    load(
+        &mut vm,
+        0x3000,
+        &[
+            "LDI R1, #3",
+            "BRzp #-2",
+            "LDI R0, #2",
+            "HALT",
+            ".FILL xFE00",
+            ".FILL xFE02",
+        ],
+    );
+
+    let mut output = vec![];
+    vm.run_until_halt(&b"x"[..], &mut output).unwrap();
+    assert_eq!(vm.reg[Reg::R0], b'x' as u16);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn reports_bad_opcode() {
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, &["OUT", ".FILL xD000"]);
+    vm.reg[Reg::R0] = b'a' as u16;
+
+    let mut output = vec![];
+    match vm.run_until_halt(&b""[..], &mut output) {
+        Err(VmError::BadOpcode { pc, instr }) => assert_eq!((pc, instr), (0x3001, 0xD000)),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(output, b"a");
+}
+
+#[test]
+fn getc_reads_from_substituted_input() {
+    let mut vm = Vm::new();
+    vm.set_input(std::io::Cursor::new(b"ab".to_vec()));
+    load(&mut vm, 0x3000, &["GETC", "ADD R1, R0, #0", "GETC"]);
+
+    vm.step_once();
+    vm.step_once();
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::R1], b'a' as u16);
+    assert_eq!(vm.reg[Reg::R0], b'b' as u16);
+    assert_eq!(vm.read_byte(), None);
+}
+
+#[test]
+fn getc_returns_sentinel_at_end_of_input() {
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, &["GETC", "ADD R1, R0, #0", "GETC", "HALT"]);
+
+    let mut output = vec![];
+    vm.run_until_halt(&b"a"[..], &mut output).unwrap();
+    assert_eq!(vm.reg[Reg::R1], b'a' as u16);
+    assert_eq!(vm.reg[Reg::R0], EOF_SENTINEL);
+}
+
+#[test]
+fn halts_at_end_of_input_when_asked() {
+    let mut vm = Vm::new();
+    vm.on_eof = EofPolicy::Halt;
+    vm.set_input(std::io::Cursor::new(vec![]));
+    load(&mut vm, 0x3000, &["IN", "ADD R1, R1, #1"]);
+
+    assert_eq!(vm.step_once(), StepResult::Halted);
+    assert_eq!(vm.reg[Reg::R1], 0);
+
+    // 轮询KBSR读到头也一样停机
+    let mut vm = Vm::new();
+    vm.on_eof = EofPolicy::Halt;
+    vm.set_input(std::io::Cursor::new(vec![]));
+    load(&mut vm, 0x3000, &["LDI R1, #0", ".FILL xFE00"]);
+    assert_eq!(vm.step_once(), StepResult::Halted);
+}
+
+#[test]
+fn jsr_and_jsrr_return_to_the_next_instruction() {
+    let source = "
+        .ORIG x3000
+        JSR SUB
+        ADD R2, R2, #1
+        LEA R3, SUB
+        JSRR R3
+        ADD R2, R2, #1
+        HALT
+SUB     ADD R1, R1, #1
+        RET
+        .END
+    ";
+    let mut vm = Vm::new();
+    vm.read_image_from_bytes(&to_bytes(&assemble(source).unwrap()), None)
+        .unwrap();
+
+    // JSR执行完时PC已经加过1，R7就是下一条指令的地址
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::R7], 0x3001);
+    assert_eq!(vm.reg[Reg::PC], 0x3006);
+    vm.step_once();
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::PC], 0x3001);
+
+    vm.step_once();
+    vm.step_once();
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::R7], 0x3004);
+    assert_eq!(vm.reg[Reg::PC], 0x3006);
+
+    let mut output = vec![];
+    vm.run_until_halt(&b""[..], &mut output).unwrap();
+    assert_eq!(vm.reg[Reg::R1], 2);
+    assert_eq!(vm.reg[Reg::R2], 2);
+}
+
+#[test]
+fn reset_restores_a_fresh_machine() {
+    let mut vm = Vm::new();
+    vm.origin = 0x4000;
+    vm.sink = None;
+    load(&mut vm, 0x3000, &["ADD R1, R1, #3", "OUT", "HALT"]);
+    vm.reg[Reg::R0] = b'a' as u16;
+    vm.step_once();
+    vm.step_once();
+    vm.step_once();
+    assert!(!vm.running);
+
+    let memory = vm.memory.as_ptr();
+    vm.reset();
+    assert!(vm.running);
+    assert_eq!(vm.reg[Reg::PC], 0x4000);
+    assert_eq!(vm.reg[Reg::R1], 0);
+    assert!(vm.memory.iter().all(|&word| word == 0));
+    // 内存没有重新分配，输出也没有被清掉
+    assert_eq!(vm.memory.as_ptr(), memory);
+    assert_eq!(vm.output, b"a");
+}
+
+/// 写什么都失败，模拟stdout被关掉
+struct BrokenPipe;
+
+impl std::io::Write for BrokenPipe {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn step_reports_structured_errors() {
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, &["TRAP x30"]);
+    match vm.step() {
+        Err(VmError::BadTrap { pc, vector }) => assert_eq!((pc, vector), (0x3000, 0x30)),
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, &["RTI"]);
+    assert!(matches!(
+        vm.step(),
+        Err(VmError::IllegalInstruction { pc: 0x3000, .. })
+    ));
+
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, &["ADD R0, R0, #1", "HALT"]);
+    assert!(!vm.step().unwrap().halted);
+    assert!(vm.step().unwrap().halted);
+}
+
+#[test]
+fn output_failure_is_an_error_not_a_panic() {
+    let mut vm = Vm::new();
+    vm.sink = Some(Box::new(BrokenPipe));
+    load(&mut vm, 0x3000, &["OUT", "HALT"]);
+    match vm.run() {
+        Err(VmError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn step_n_stops_at_the_first_non_continued_step() {
+    let mut vm = Vm::new();
+    load(
+        &mut vm,
+        0x3000,
+        &["ADD R0, R0, #1", "ADD R0, R0, #1", "HALT"],
+    );
+
+    assert_eq!(vm.step_n(1), StepResult::Continued);
+    assert_eq!(vm.reg[Reg::R0], 1);
+    assert_eq!(vm.step_n(10), StepResult::Halted);
+    assert_eq!(vm.reg[Reg::R0], 2);
+}
+
+#[test]
+fn run_until_stops_before_the_target_instruction() {
+    let mut vm = Vm::new();
+    vm.set_input(std::io::Cursor::new(vec![]));
+    load(
+        &mut vm,
+        0x3000,
+        &["AND R0, R0, #0", "ADD R0, R0, #1", "ADD R0, R0, #1", "HALT"],
+    );
+
+    assert!(vm.run_until(0x3002).unwrap());
+    assert_eq!(vm.reg[Reg::PC], 0x3002);
+    assert_eq!(vm.reg[Reg::R0], 1);
+
+    // 已经在目标上时至少执行一条，到不了就一直跑到HALT
+    assert!(!vm.run_until(0x3002).unwrap());
+    assert_eq!(vm.reg[Reg::R0], 2);
+}
+
+#[test]
+fn run_until_respects_max_instructions() {
+    let mut vm = Vm::new();
+    vm.max_instructions = Some(50);
+    load(&mut vm, 0x3000, &["ADD R0, R0, #0", "BRnzp #-2"]);
+
+    assert!(matches!(
+        vm.run_until(0x4000),
+        Err(VmError::InstructionLimit { limit: 50, .. })
+    ));
+}
@@ -0,0 +1,42 @@
+use lc_3_vm::search::{find_string, find_words, StringEncoding};
+
+#[test]
+fn finds_single_words_and_sequences() {
+    let mut memory = vec![0u16; 0x100];
+    memory[0x10] = 0xABCD;
+    memory[0x11] = 0x1234;
+    memory[0x40] = 0xABCD;
+
+    assert_eq!(find_words(&memory, &[0xABCD]), [0x10, 0x40]);
+    assert_eq!(find_words(&memory, &[0xABCD, 0x1234]), [0x10]);
+    assert!(find_words(&memory, &[]).is_empty());
+}
+
+#[test]
+fn finds_strings_in_both_encodings() {
+    let mut memory = vec![0u16; 0x100];
+    for (i, b) in b"hello".iter().enumerate() {
+        memory[0x20 + i] = *b as u16;
+    }
+    // "hello world"按PUTSP打包：奇数长度的"hello"最后一个字高8位是' '
+    for (i, pair) in b"hello world".chunks(2).enumerate() {
+        memory[0x80 + i] = u16::from_le_bytes([pair[0], *pair.get(1).unwrap_or(&0)]);
+    }
+
+    assert_eq!(
+        find_string(&memory, b"hello"),
+        [(0x20, StringEncoding::Puts), (0x80, StringEncoding::Putsp)]
+    );
+    assert_eq!(
+        find_string(&memory, b"world"),
+        [(0x83, StringEncoding::Putsp)]
+    );
+    assert!(
+        find_string(&memory, b"lo w").is_empty(),
+        "PUTSP strings only start at the low byte of a word"
+    );
+    assert_eq!(
+        find_string(&memory, b"o world"),
+        [(0x82, StringEncoding::Putsp)]
+    );
+}
@@ -0,0 +1,69 @@
+use lc_3_vm::register::Reg;
+use lc_3_vm::snapshot::SnapshotError;
+use lc_3_vm::Vm;
+
+#[test]
+fn round_trips_full_state() {
+    let mut vm = Vm::new();
+    vm.memory[0x3000] = 0xF025;
+    vm.memory[0xFFFF] = 0xBEEF;
+    vm.reg[Reg::R3] = 0x1234;
+    vm.reg[Reg::PC] = 0x3005;
+    vm.saved_ssp = 0x2FF0;
+    vm.running = false;
+
+    let mut restored = Vm::new();
+    restored
+        .restore_snapshot_bytes(&vm.snapshot_bytes())
+        .unwrap();
+
+    assert_eq!(restored.memory, vm.memory);
+    assert_eq!(restored.reg, vm.reg);
+    assert_eq!(restored.saved_ssp, 0x2FF0);
+    assert!(!restored.running);
+}
+
+#[test]
+fn rejects_foreign_or_damaged_snapshots() {
+    let mut vm = Vm::new();
+    let mut data = Vm::new().snapshot_bytes();
+
+    assert!(matches!(
+        vm.restore_snapshot_bytes(b"LC3\0rest"),
+        Err(SnapshotError::BadMagic)
+    ));
+
+    data[5] = 99;
+    assert!(matches!(
+        vm.restore_snapshot_bytes(&data),
+        Err(SnapshotError::UnsupportedVersion(99))
+    ));
+
+    data[5] = 1;
+    data.truncate(data.len() - 1);
+    assert!(matches!(
+        vm.restore_snapshot_bytes(&data),
+        Err(SnapshotError::Truncated)
+    ));
+}
+
+#[test]
+fn rejects_unreasonable_memory_sizes() {
+    let mut vm = Vm::new();
+
+    let small = Vm::with_memory_size(0x100).snapshot_bytes();
+    assert!(matches!(
+        vm.restore_snapshot_bytes(&small),
+        Err(SnapshotError::MemorySize(0x100))
+    ));
+
+    // 内存字数在内存数据前面的4个字节里
+    let mut huge = Vm::new().snapshot_bytes();
+    let at = huge.len() - 2 * 0x10000 - 4;
+    huge[at..at + 4].copy_from_slice(&0x10001u32.to_be_bytes());
+    assert!(matches!(
+        vm.restore_snapshot_bytes(&huge),
+        Err(SnapshotError::MemorySize(0x10001))
+    ));
+    assert_eq!(vm.memory.len(), 0x10000);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::load;
+use lc_3_vm::register::Reg;
+use lc_3_vm::state::MachineState;
+use lc_3_vm::Vm;
+use serde_json::{json, Value};
+
+#[test]
+fn dumps_registers_psr_and_sparse_memory() {
+    let mut vm = Vm::new();
+    load(
+        &mut vm,
+        0x3000,
+        &[
+            "AND R0, R0, #0",
+            "ADD R0, R0, #-3",
+            "ADD R7, R0, #10",
+            "HALT",
+        ],
+    );
+    vm.run_until_halt(&b""[..], std::io::sink()).unwrap();
+
+    let state = MachineState::capture(&vm.reg, &vm.memory, 4, true);
+    let json: Value = serde_json::from_str(&state.to_json()).unwrap();
+
+    assert_eq!(json["registers"]["R0"], 0xFFFD);
+    assert_eq!(json["registers"]["R7"], 7);
+    assert_eq!(json["registers"].as_object().unwrap().len(), 8);
+    assert_eq!(json["pc"], vm.reg[Reg::PC]);
+    assert_eq!(json["cond"], "P");
+    assert_eq!(json["psr"], vm.reg[Reg::PSR]);
+    assert_eq!(json["instructions"], 4);
+
+    // 只有程序本身的4个字非零
+    assert_eq!(
+        json["memory"],
+        json!([
+            { "addr": 0x3000, "value": vm.memory[0x3000] },
+            { "addr": 0x3001, "value": vm.memory[0x3001] },
+            { "addr": 0x3002, "value": vm.memory[0x3002] },
+            { "addr": 0x3003, "value": vm.memory[0x3003] },
+        ])
+    );
+}
+
+#[test]
+fn memory_is_left_out_unless_asked_for() {
+    let vm = Vm::new();
+    let state = MachineState::capture(&vm.reg, &vm.memory, 0, false);
+    let json: Value = serde_json::from_str(&state.to_json()).unwrap();
+    assert!(json.get("memory").is_none());
+    assert_eq!(json["pc"], 0x3000);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::load;
+use lc_3_vm::opcodes::OpCodes;
+use lc_3_vm::register::Reg;
+use lc_3_vm::{MemoryWrite, Vm, VmError};
+
+/// 把汇编行依次放到0x3000开始的内存里
+fn vm_with(lines: &[&str]) -> Vm {
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, lines);
+    vm
+}
+
+#[test]
+fn step_reports_what_the_instruction_did() {
+    let mut vm = vm_with(&["ADD R1, R1, #5", "ST R1, #1", "HALT", ".FILL x1234"]);
+
+    let add = vm.step().unwrap();
+    assert_eq!((add.pc, add.instr), (0x3000, 0x1265));
+    assert_eq!(add.opcode, OpCodes::OP_ADD);
+    let changed: Vec<_> = add.changed_registers().map(|(r, ..)| r).collect();
+    assert_eq!(
+        changed,
+        vec![
+            Reg::R1 as usize,
+            Reg::PC as usize,
+            Reg::COND as usize,
+            Reg::PSR as usize
+        ]
+    );
+    assert_eq!((add.before[Reg::R1], add.after[Reg::R1]), (0, 5));
+    assert!(add.writes.is_empty());
+    assert!(!add.halted);
+
+    let st = vm.step().unwrap();
+    assert_eq!((st.pc, st.instr), (0x3001, 0x3201));
+    assert_eq!(
+        st.writes,
+        vec![MemoryWrite {
+            addr: 0x3003,
+            old: 0x1234,
+            new: 5
+        }]
+    );
+    assert_eq!(
+        st.changed_registers().collect::<Vec<_>>(),
+        vec![(Reg::PC as usize, 0x3001, 0x3002)]
+    );
+
+    let halt = vm.step().unwrap();
+    assert_eq!(halt.instr, 0xF025);
+    assert_eq!(halt.opcode, OpCodes::OP_TRAP);
+    assert!(halt.halted);
+}
+
+#[test]
+fn step_reports_bad_instructions() {
+    let mut vm = vm_with(&[".FILL xD000"]);
+
+    assert!(matches!(
+        vm.step(),
+        Err(VmError::BadOpcode {
+            pc: 0x3000,
+            instr: 0xD000
+        })
+    ));
+}
@@ -0,0 +1,261 @@
+mod common;
+
+use common::load;
+use lc_3_vm::register::Reg;
+use lc_3_vm::rng::Rng;
+use lc_3_vm::{StepResult, TrapCode, UnknownTrap, Vm, EOF_SENTINEL};
+
+#[test]
+fn use_os_jumps_through_trap_vector_table() {
+    let mut vm = Vm::new();
+    vm.use_os = true;
+    vm.memory[0x21] = 0x0300;
+    load(&mut vm, 0x0300, &["ADD R1, R1, #1", "RET"]);
+    load(&mut vm, 0x3000, &["OUT"]);
+
+    assert_eq!(vm.step_once(), StepResult::Continued);
+    assert_eq!(vm.reg[Reg::PC], 0x0300);
+    assert_eq!(vm.reg[Reg::R7], 0x3001);
+
+    vm.step_once();
+    vm.step_once();
+    assert_eq!(vm.reg[Reg::R1], 1);
+    assert_eq!(vm.reg[Reg::PC], 0x3001);
+}
+
+#[test]
+fn os_halt_routine_stops_the_machine_through_mcr() {
+    let mut vm = Vm::new();
+    vm.use_os = true;
+    vm.memory[0x25] = 0x0400;
+    // STI R0, MCR：R0是0，清掉了MCR的第15位
+    load(
+        &mut vm,
+        0x0400,
+        &["AND R0, R0, #0", "STI R0, #0", ".FILL xFFFE"],
+    );
+    load(&mut vm, 0x3000, &["HALT"]);
+
+    vm.step_once();
+    vm.step_once();
+    assert_eq!(vm.step_once(), StepResult::Halted);
+    assert!(!vm.running);
+}
+
+#[test]
+fn native_traps_without_use_os() {
+    let mut vm = Vm::new();
+    vm.memory[0x25] = 0x0400;
+    load(&mut vm, 0x3000, &["HALT"]);
+
+    assert_eq!(vm.step_once(), StepResult::Halted);
+    assert_eq!(vm.reg[Reg::PC], 0x3001);
+}
+
+#[test]
+fn puts_writes_the_low_byte_of_each_word_unchanged() {
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, &["LEA R0, #2", "PUTS", "HALT"]);
+    // 高8位是垃圾的字只输出低8位；0xC9原样输出一个字节，不变成UTF-8的两个字节
+    vm.memory[0x3003] = 0x4148;
+    vm.memory[0x3004] = 0x00C9;
+    vm.memory[0x3005] = 0x0000;
+
+    let mut output = vec![];
+    vm.run_until_halt(&b""[..], &mut output).unwrap();
+    assert_eq!(output, [0x48, 0xC9]);
+}
+
+#[test]
+fn every_output_path_writes_raw_bytes() {
+    let mut vm = Vm::new();
+    load(
+        &mut vm,
+        0x3000,
+        &[
+            "LD R0, #5",
+            "OUT",
+            "STI R0, #4",
+            "LEA R0, #4",
+            "PUTSP",
+            "HALT",
+            ".FILL x00C9",
+            ".FILL xFE06",
+            ".FILL xBACD",
+            ".FILL x0000",
+        ],
+    );
+
+    let mut output = vec![];
+    vm.run_until_halt(&b""[..], &mut output).unwrap();
+    assert_eq!(output, [0xC9, 0xC9, 0xCD, 0xBA]);
+}
+
+#[test]
+fn in_echoes_the_key_but_getc_does_not() {
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, &["GETC", "IN", "HALT"]);
+
+    let mut output = vec![];
+    vm.run_until_halt(&b"xy"[..], &mut output).unwrap();
+    assert_eq!(output, b"Enter a character: y");
+    assert_eq!(vm.reg[Reg::R0], b'y' as u16);
+}
+
+#[test]
+fn readline_is_an_extension() {
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, &["TRAP x26"]);
+    assert_eq!(
+        vm.step_once(),
+        StepResult::BadInstruction {
+            pc: 0x3000,
+            instr: 0xF026
+        }
+    );
+}
+
+#[test]
+fn readline_reads_up_to_newline_or_limit() {
+    let mut vm = Vm::new();
+    vm.extensions = true;
+    load(
+        &mut vm,
+        0x3000,
+        &[
+            "LD R0, #5",
+            "AND R1, R1, #0",
+            "ADD R1, R1, #8",
+            "TRAP x26",
+            "ADD R2, R1, #0",
+            "HALT",
+            ".FILL x4000",
+        ],
+    );
+
+    let mut output = vec![];
+    vm.run_until_halt(&b"hi there\nmore"[..], &mut output)
+        .unwrap();
+    assert_eq!(vm.reg[Reg::R2], 8);
+    let line = vm.memory[0x4000..0x4009].to_vec();
+    let expected = b"hi there\0".iter().map(|&b| b as u16).collect::<Vec<_>>();
+    assert_eq!(line, expected);
+    // 读满8个字符就停了，换行还留在输入里
+    assert_eq!(output, b"hi there");
+
+    let mut vm = Vm::new();
+    vm.extensions = true;
+    load(
+        &mut vm,
+        0x3000,
+        &[
+            "LD R0, #3",
+            "LD R1, #3",
+            "TRAP x26",
+            "HALT",
+            ".FILL x4000",
+            ".FILL #10",
+        ],
+    );
+    vm.run_until_halt(&b"ok\nrest"[..], std::io::sink())
+        .unwrap();
+    assert_eq!(vm.reg[Reg::R1], 2);
+    assert_eq!(&vm.memory[0x4000..0x4003], &[b'o' as u16, b'k' as u16, 0]);
+}
+
+#[test]
+fn readline_tells_an_empty_line_from_end_of_input() {
+    let mut vm = Vm::new();
+    vm.extensions = true;
+    load(
+        &mut vm,
+        0x3000,
+        &[
+            "LD R0, #6",
+            "LD R1, #6",
+            "TRAP x26",
+            "ADD R2, R1, #0",
+            "LD R1, #3",
+            "TRAP x26",
+            "HALT",
+            ".FILL x4000",
+            ".FILL #10",
+        ],
+    );
+
+    vm.run_until_halt(&b"\n"[..], std::io::sink()).unwrap();
+    // 第一次只读到回车，是空行；第二次什么都没读到，是EOF
+    assert_eq!(vm.reg[Reg::R2], 0);
+    assert_eq!(vm.reg[Reg::R1], EOF_SENTINEL);
+    assert_eq!(vm.memory[0x4000], 0);
+}
+
+#[test]
+fn trap_code_try_from_vector() {
+    assert_eq!(TrapCode::try_from(0x20), Ok(TrapCode::GETC));
+    assert_eq!(TrapCode::try_from(0x25), Ok(TrapCode::HALT));
+    assert_eq!(TrapCode::try_from(0x26), Ok(TrapCode::READLINE));
+    assert!(TrapCode::READLINE.is_extension());
+    assert!(!TrapCode::PUTSP.is_extension());
+    assert_eq!(TrapCode::PUTSP.name(), "PUTSP");
+
+    assert_eq!(TrapCode::try_from(0x1F), Err(UnknownTrap(0x1F)));
+    assert_eq!(TrapCode::try_from(0x29), Err(UnknownTrap(0x29)));
+    assert_eq!(UnknownTrap(0x30).to_string(), "unknown trap vector 0x30");
+}
+
+#[test]
+fn putint_prints_r0_as_signed_decimal() {
+    let mut vm = Vm::new();
+    vm.extensions = true;
+    load(
+        &mut vm,
+        0x3000,
+        &[
+            "AND R0, R0, #0",
+            "TRAP x27",
+            "ADD R0, R0, #-12",
+            "TRAP x27",
+            "LD R0, #2",
+            "TRAP x27",
+            "HALT",
+            ".FILL x7FFF",
+        ],
+    );
+
+    let mut output = vec![];
+    vm.run_until_halt(&b""[..], &mut output).unwrap();
+    assert_eq!(output, b"0-1232767");
+
+    let mut vm = Vm::new();
+    load(&mut vm, 0x3000, &["TRAP x27"]);
+    assert!(matches!(
+        vm.step_once(),
+        StepResult::BadInstruction { pc: 0x3000, .. }
+    ));
+}
+
+#[test]
+fn rand_is_reproducible_with_the_same_seed() {
+    let program = ["TRAP x28", "ADD R1, R0, #0", "TRAP x28", "HALT"];
+    let run = |seed| {
+        let mut vm = Vm::new();
+        vm.extensions = true;
+        vm.rng = Rng::new(seed);
+        load(&mut vm, 0x3000, &program);
+        vm.run_until_halt(&b""[..], &mut vec![]).unwrap();
+        (vm.reg[Reg::R1], vm.reg[Reg::R0])
+    };
+
+    let first = run(42);
+    assert_eq!(first, run(42));
+    assert_ne!(first.0, first.1);
+    assert_ne!(first, run(43));
+
+    // 和rng.rs里写的算法一致：xorshift32(13, 17, 5)，取高16位
+    let mut rng = Rng::new(1);
+    assert_eq!(rng.next_u16(), 0x0004);
+    assert_eq!(rng.next_u16(), 0x0408);
+    // 种子0会被换成非0值，不会一直输出0
+    assert_ne!(Rng::new(0).next_u16(), 0);
+}